@@ -1,7 +1,6 @@
 use crate::{DependencySource, SharedDependency};
-use semver::{Comparator, Op, Prerelease, VersionReq};
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use semver::{Comparator, Op, Prerelease, Version, VersionReq};
+use std::collections::BTreeSet;
 
 /// For a given package, this struct keeps track of the versions that have been seen.
 /// It actively tries to minimize the number of versions that are kept.
@@ -9,45 +8,38 @@ use std::collections::HashMap;
 /// In particular:
 ///
 /// - If the same version requirement appears more than once, only one instance is kept.
-/// - If different version requirements appear, all instances are kept.
+/// - If different version requirements appear, all instances are kept unless they can be merged.
+///   Requirements are only merged if they come from the same registry. A new requirement is
+///   merged into the first requirement it overlaps with, in insertion order, so that the outcome
+///   only depends on the order in which requirements are seen.
 /// - If the same version requirement appears more than once, with default features enabled in one
 ///   case and disabled in another, only the disabled instance is kept.
 #[derive(Default)]
 pub(crate) struct MinimalVersionSet {
-    seen: HashMap<DependencySource, bool>,
+    /// Each source, along with whether default features are enabled for it, in insertion order.
+    seen: Vec<(DependencySource, bool)>,
 }
 
 impl MinimalVersionSet {
     pub(crate) fn insert(&mut self, dep: SharedDependency) {
-        if let Some(default_features) = self.seen.get_mut(&dep.source) {
+        if let Some((_, default_features)) = self
+            .seen
+            .iter_mut()
+            .find(|(source, _)| *source == dep.source)
+        {
             *default_features &= dep.default_features;
             return;
         }
 
-        if let DependencySource::Version(version_req) = &dep.source {
-            let mut swap = None;
-            for (source, default_features) in self.seen.iter() {
-                let DependencySource::Version(other_version_req) = source else {
-                    continue;
-                };
-                if let Some(merged) = try_merge(version_req, other_version_req) {
-                    swap = Some((
-                        source.clone(),
-                        merged,
-                        *default_features && dep.default_features,
-                    ));
-                    break;
-                }
-            }
-            if let Some((source, merged, default_features)) = swap {
-                self.seen.remove(&source);
-                self.seen
-                    .insert(DependencySource::Version(merged), default_features);
+        for (source, default_features) in &mut self.seen {
+            if let Some(merged) = try_merge_sources(&dep.source, source) {
+                *source = merged;
+                *default_features &= dep.default_features;
                 return;
             }
         }
 
-        self.seen.insert(dep.source, dep.default_features);
+        self.seen.push((dep.source, dep.default_features));
     }
 
     pub(crate) fn into_iter(self) -> impl Iterator<Item = SharedDependency> {
@@ -62,94 +54,451 @@ impl MinimalVersionSet {
     pub(crate) fn len(&self) -> usize {
         self.seen.len()
     }
+
+    /// Replaces the version requirements in the set with a single one that starts at `locked`,
+    /// the version resolved in `Cargo.lock`, while still satisfying every requirement in the set.
+    ///
+    /// The set is left untouched if it contains anything other than version requirements
+    /// from the same registry, or if `locked` doesn't satisfy all of them.
+    /// If any requirement contains pre-release identifiers, the result is pinned to `=locked`.
+    pub(crate) fn merge_into_locked(&mut self, locked: &Version) {
+        let Some((registry, requirements)) = self.requirements() else {
+            return;
+        };
+        if !requirements.iter().all(|version| version.matches(locked)) {
+            return;
+        }
+
+        let locked_range = VersionRange {
+            lower: locked.clone(),
+            upper: None,
+        };
+        let merged = requirements
+            .iter()
+            .try_fold(locked_range, |range, requirement| {
+                Some(range.intersect(&VersionRange::from_req(requirement)?))
+            })
+            .filter(|_| locked.pre == Prerelease::EMPTY)
+            .map(|range| range.to_req())
+            .unwrap_or_else(|| VersionReq {
+                comparators: vec![Comparator {
+                    op: Op::Exact,
+                    major: locked.major,
+                    minor: Some(locked.minor),
+                    patch: Some(locked.patch),
+                    pre: locked.pre.clone(),
+                }],
+            });
+        let source = registry_source(registry, merged);
+        let default_features = self
+            .seen
+            .iter()
+            .all(|(_, default_features)| *default_features);
+        self.seen = vec![(source, default_features)];
+    }
+
+    /// Picks the highest (or the lowest) version requirement in the set, ordered by the
+    /// smallest version they match.
+    ///
+    /// Returns `None` if the set contains anything other than version requirements from
+    /// the same registry, or requirements with pre-release identifiers.
+    /// The picked dependency only enables default features if all of them did.
+    pub(crate) fn pick(&self, highest: bool) -> Option<SharedDependency> {
+        let (registry, requirements) = self.requirements()?;
+        let ranges = requirements
+            .into_iter()
+            .map(|requirement| Some((VersionRange::from_req(requirement)?, requirement)))
+            .collect::<Option<Vec<_>>>()?;
+        let key = |(range, _): &(VersionRange, &VersionReq)| {
+            (
+                range.lower.clone(),
+                range.upper.is_none(),
+                range.upper.clone(),
+            )
+        };
+        let (_, requirement) = if highest {
+            ranges.into_iter().max_by_key(key)?
+        } else {
+            ranges.into_iter().min_by_key(key)?
+        };
+        Some(SharedDependency {
+            default_features: self
+                .seen
+                .iter()
+                .all(|(_, default_features)| *default_features),
+            source: registry_source(registry, requirement.clone()),
+        })
+    }
+
+    /// Returns `true` if the set holds several version requirements from the same registry,
+    /// and therefore no single version can satisfy all of them.
+    ///
+    /// Requirements are merged as soon as they overlap, so any two requirements left in the set
+    /// are disjoint—unless they contain pre-release identifiers, which are only merged when
+    /// identical.
+    pub(crate) fn has_disjoint_requirements(&self) -> bool {
+        let Some((_, requirements)) = self.requirements() else {
+            return false;
+        };
+        requirements.len() > 1
+            && requirements
+                .iter()
+                .all(|requirement| VersionRange::from_req(requirement).is_some())
+    }
+
+    /// Returns the registry (`None` for crates.io) and the version requirements in the set,
+    /// if all of them are version requirements for the same registry.
+    fn requirements(&self) -> Option<(Option<&str>, Vec<&VersionReq>)> {
+        let mut registries = BTreeSet::new();
+        let mut requirements = Vec::new();
+        for (source, _) in &self.seen {
+            let (registry, version) = match source {
+                DependencySource::Version(version) => (None, version),
+                DependencySource::Registry { registry, version } => {
+                    (Some(registry.as_str()), version)
+                }
+                _ => return None,
+            };
+            registries.insert(registry);
+            requirements.push(version);
+        }
+        if registries.len() != 1 {
+            return None;
+        }
+        Some((registries.pop_first().flatten(), requirements))
+    }
 }
 
-/// Tries to merge two version requirements into a single version requirement.
+fn registry_source(registry: Option<&str>, version: VersionReq) -> DependencySource {
+    match registry {
+        None => DependencySource::Version(version),
+        Some(registry) => DependencySource::Registry {
+            registry: registry.to_owned(),
+            version,
+        },
+    }
+}
+
+/// Tries to merge two sources into a single one.
+///
+/// Only sources that differ exclusively by their version requirement can be merged—i.e.
+/// two crates.io dependencies, or two dependencies from the same named registry.
+pub(crate) fn try_merge_sources(
+    first: &DependencySource,
+    second: &DependencySource,
+) -> Option<DependencySource> {
+    match (first, second) {
+        (DependencySource::Version(first), DependencySource::Version(second)) => {
+            try_merge(first, second).map(DependencySource::Version)
+        }
+        (
+            DependencySource::Registry {
+                registry: first_registry,
+                version: first,
+            },
+            DependencySource::Registry {
+                registry: second_registry,
+                version: second,
+            },
+        ) if first_registry == second_registry => {
+            try_merge(first, second).map(|version| DependencySource::Registry {
+                registry: first_registry.clone(),
+                version,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Tries to merge two version requirements into a single version requirement,
+/// matching exactly the versions that satisfy both of them.
 ///
-/// We handle:
+/// If both requirements are the same, the requirement is returned as-is.
+/// Otherwise, their intersection is computed and converted back to the shortest
+/// equivalent requirement—e.g. `^1.2` and `>=1.3.1, <1.9` are merged into `>=1.3.1, <1.9.0`,
+/// while `^1` and `~1.4` are merged into `~1.4`.
 ///
-/// - The case where both version requirements are the same.
-/// - The case where one version requirement is a wildcard and the other isn't.
-/// - The case where both version requirements are simple carets—e.g. `^1.2` and `^1.3.1`.
-///   In this case, we can merge them into `^1.3.1`.
+/// Returns `None` if no version satisfies both requirements, or if either of them
+/// contains pre-release identifiers: those are only merged when they are identical.
 fn try_merge(first: &VersionReq, second: &VersionReq) -> Option<VersionReq> {
     if first == second {
         return Some(first.clone());
     }
 
-    if first == &VersionReq::STAR && second != &VersionReq::STAR {
-        // First is wildcard, second isn't
-        return Some(second.clone());
+    let range = VersionRange::from_req(first)?.intersect(&VersionRange::from_req(second)?);
+    if range.is_empty() {
+        return None;
     }
+    Some(range.to_req())
+}
 
-    if first != &VersionReq::STAR && second == &VersionReq::STAR {
-        // Second is wildcard, first isn't
-        return Some(first.clone());
+/// The smallest version above all the versions that start with the given components,
+/// e.g. `1.3.0` for `1.2` or `1.3.0` for `1.2.18446744073709551615`.
+///
+/// Returns `None` if there's no such version, i.e. the range above them is unbounded.
+fn next_version(major: u64, minor: Option<u64>, patch: Option<u64>) -> Option<Version> {
+    match (minor, patch) {
+        (Some(minor), Some(patch)) => match patch.checked_add(1) {
+            Some(patch) => Some(Version::new(major, minor, patch)),
+            None => next_version(major, Some(minor), None),
+        },
+        (Some(minor), None) => match minor.checked_add(1) {
+            Some(minor) => Some(Version::new(major, minor, 0)),
+            None => next_version(major, None, None),
+        },
+        (None, _) => Some(Version::new(major.checked_add(1)?, 0, 0)),
     }
+}
 
-    let first = as_simple_caret(first)?;
-    let second = as_simple_caret(second)?;
-    if first.major != second.major {
-        return None;
+/// The set of versions matched by a version requirement without pre-release identifiers,
+/// represented as the half-open interval `[lower, upper)`.
+///
+/// Since pre-release versions never match a comparator without pre-release identifiers,
+/// `>1.2.3` and `>=1.2.4` (or `<=1.2.3` and `<1.2.4`) are equivalent for our purposes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct VersionRange {
+    lower: Version,
+    /// `None` if the range is unbounded.
+    upper: Option<Version>,
+}
+
+impl VersionRange {
+    fn any() -> Self {
+        Self {
+            lower: Version::new(0, 0, 0),
+            upper: None,
+        }
+    }
+
+    /// Returns `None` if the requirement contains pre-release identifiers or an unknown operator.
+    fn from_req(req: &VersionReq) -> Option<Self> {
+        req.comparators
+            .iter()
+            .try_fold(Self::any(), |range, comparator| {
+                Some(range.intersect(&Self::from_comparator(comparator)?))
+            })
     }
-    if first.major == 0 {
-        if first.minor != second.minor {
+
+    fn from_comparator(comparator: &Comparator) -> Option<Self> {
+        if comparator.pre != Prerelease::EMPTY {
             return None;
         }
-        if first.minor == Some(0) {
-            return None;
+        let Comparator {
+            major,
+            minor,
+            patch,
+            ..
+        } = *comparator;
+        // The smallest version matching the comparator's components, e.g. `1.2.0` for `1.2`,
+        // and the smallest version above it that doesn't, e.g. `1.3.0` for `1.2`.
+        let base = Version::new(major, minor.unwrap_or(0), patch.unwrap_or(0));
+        let next = next_version(major, minor, minor.and(patch));
+        let range = |lower: Version, upper: Option<Version>| Some(Self { lower, upper });
+        match comparator.op {
+            Op::Exact | Op::Wildcard => range(base, next),
+            // Nothing is greater than the highest possible version.
+            Op::Greater => range(next?, None),
+            Op::GreaterEq => range(base, None),
+            Op::Less => range(Version::new(0, 0, 0), Some(base)),
+            Op::LessEq => range(Version::new(0, 0, 0), next),
+            Op::Tilde => match minor {
+                Some(minor) => range(base, next_version(major, Some(minor), None)),
+                None => range(base, next),
+            },
+            Op::Caret => match (major, minor, patch) {
+                (0, None, _) | (0, Some(0), _) => range(base, next),
+                (0, Some(minor), _) => range(base, next_version(0, Some(minor), None)),
+                _ => range(base, next_version(major, None, None)),
+            },
+            _ => None,
         }
-        let comparator = Comparator {
-            op: Op::Caret,
-            major: second.major,
-            minor: second.minor,
-            patch: first.patch.max(second.patch),
-            pre: Prerelease::EMPTY,
+    }
+
+    fn intersect(&self, other: &Self) -> Self {
+        let upper = match (&self.upper, &other.upper) {
+            (Some(a), Some(b)) => Some(a.min(b).clone()),
+            (a, b) => a.as_ref().or(b.as_ref()).cloned(),
         };
-        return Some(VersionReq {
-            comparators: vec![comparator],
-        });
-    }
-    let comparator = match first.minor.cmp(&second.minor) {
-        Ordering::Less => Comparator {
-            op: Op::Caret,
-            major: second.major,
-            minor: second.minor,
-            patch: second.patch,
-            pre: Prerelease::EMPTY,
-        },
-        Ordering::Greater => Comparator {
-            op: Op::Caret,
-            major: first.major,
-            minor: first.minor,
-            patch: first.patch,
-            pre: Prerelease::EMPTY,
-        },
-        Ordering::Equal => Comparator {
-            op: Op::Caret,
-            major: first.major,
-            minor: first.minor,
-            patch: first.patch.max(second.patch),
+        Self {
+            lower: self.lower.clone().max(other.lower.clone()),
+            upper,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.upper
+            .as_ref()
+            .is_some_and(|upper| upper <= &self.lower)
+    }
+
+    /// Converts the range back into the shortest equivalent version requirement.
+    ///
+    /// A single caret, tilde or exact comparator is preferred, in this order,
+    /// falling back to a `>=lower, <upper` pair if none of them matches the range.
+    fn to_req(&self) -> VersionReq {
+        let comparator = |op, lower: Version| Comparator {
+            op,
+            major: lower.major,
+            minor: Some(lower.minor),
+            patch: Some(lower.patch),
             pre: Prerelease::EMPTY,
-        },
-    };
-    Some(VersionReq {
-        comparators: vec![comparator],
-    })
+        };
+
+        if self.upper.is_some() {
+            for precision in 1..=3 {
+                for op in [Op::Caret, Op::Tilde, Op::Exact] {
+                    let mut candidate = comparator(op, self.lower.clone());
+                    if precision < 3 {
+                        if candidate.patch != Some(0) {
+                            continue;
+                        }
+                        candidate.patch = None;
+                    }
+                    if precision < 2 {
+                        if candidate.minor != Some(0) {
+                            continue;
+                        }
+                        candidate.minor = None;
+                    }
+                    if Self::from_comparator(&candidate).as_ref() == Some(self) {
+                        return VersionReq {
+                            comparators: vec![candidate],
+                        };
+                    }
+                }
+            }
+        }
+
+        let mut comparators = Vec::new();
+        if self.lower != Version::new(0, 0, 0) {
+            comparators.push(comparator(Op::GreaterEq, self.lower.clone()));
+        }
+        if let Some(upper) = &self.upper {
+            comparators.push(comparator(Op::Less, upper.clone()));
+        }
+        VersionReq { comparators }
+    }
 }
 
-/// A `VersionReq` is "a simple caret" if it contains a single comparator with a `^` prefix
-/// and there are no pre-release or build identifiers.
-fn as_simple_caret(req: &VersionReq) -> Option<&Comparator> {
-    if req.comparators.len() != 1 {
-        return None;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(req: &str) -> Option<VersionRange> {
+        VersionRange::from_req(&req.parse().unwrap())
     }
-    let comp = &req.comparators[0];
-    if comp.op != Op::Caret {
-        return None;
+
+    fn bounds(lower: &str, upper: Option<&str>) -> Option<VersionRange> {
+        Some(VersionRange {
+            lower: lower.parse().unwrap(),
+            upper: upper.map(|upper| upper.parse().unwrap()),
+        })
     }
-    if comp.pre != Prerelease::EMPTY {
-        return None;
+
+    fn merge(first: &str, second: &str) -> Option<String> {
+        try_merge(&first.parse().unwrap(), &second.parse().unwrap()).map(|req| req.to_string())
+    }
+
+    #[test]
+    fn ranges_of_single_comparators() {
+        assert_eq!(range("~1.2.3"), bounds("1.2.3", Some("1.3.0")));
+        assert_eq!(range("~1"), bounds("1.0.0", Some("2.0.0")));
+        assert_eq!(range("=1.2.3"), bounds("1.2.3", Some("1.2.4")));
+        assert_eq!(range("=1.2"), bounds("1.2.0", Some("1.3.0")));
+        assert_eq!(range("1.*"), bounds("1.0.0", Some("2.0.0")));
+        assert_eq!(range("1.2.*"), bounds("1.2.0", Some("1.3.0")));
+        assert_eq!(range(">1.2.3"), bounds("1.2.4", None));
+        assert_eq!(range("<=1.2"), bounds("0.0.0", Some("1.3.0")));
+        assert_eq!(range(">=1.2, <1.5"), bounds("1.2.0", Some("1.5.0")));
+    }
+
+    #[test]
+    fn ranges_of_zero_major_carets() {
+        assert_eq!(range("^0.2.3"), bounds("0.2.3", Some("0.3.0")));
+        assert_eq!(range("^0.0.3"), bounds("0.0.3", Some("0.0.4")));
+        assert_eq!(range("^0.0"), bounds("0.0.0", Some("0.1.0")));
+        assert_eq!(range("^0"), bounds("0.0.0", Some("1.0.0")));
+    }
+
+    #[test]
+    fn pre_release_requirements_have_no_range() {
+        assert_eq!(range("^1.0.0-beta.1"), None);
+        assert_eq!(range(">=1.0.0, <2.0.0-alpha"), None);
+        assert_eq!(
+            merge("^1.0.0-beta.1", "^1.0.0-beta.1"),
+            Some("^1.0.0-beta.1".into())
+        );
+        assert_eq!(merge("^1.0.0-beta.1", "^1.0.0-beta.2"), None);
+    }
+
+    #[test]
+    fn empty_intersections() {
+        assert!(range("^1")
+            .unwrap()
+            .intersect(&range("^2").unwrap())
+            .is_empty());
+        assert!(range("<1.2")
+            .unwrap()
+            .intersect(&range(">=1.2").unwrap())
+            .is_empty());
+        assert!(!range("^1")
+            .unwrap()
+            .intersect(&range("~1.9").unwrap())
+            .is_empty());
+        assert_eq!(merge("^1", "^2"), None);
+        assert_eq!(merge("=1.2.3", ">1.2.3"), None);
+    }
+
+    #[test]
+    fn intersections_are_converted_to_the_shortest_requirement() {
+        assert_eq!(
+            merge("^1.2", ">=1.3.1, <1.9"),
+            Some(">=1.3.1, <1.9.0".into())
+        );
+        assert_eq!(merge("^1", "~1.4"), Some("~1.4".into()));
+        assert_eq!(merge("^1.0.0", "^1.1.5"), Some("^1.1.5".into()));
+        assert_eq!(merge("^1", ">=1.2, <1.9"), Some(">=1.2.0, <1.9.0".into()));
+        assert_eq!(merge("0.2", "=0.2.4"), Some("=0.2.4".into()));
+        assert_eq!(merge(">=1.2.3", "*"), Some(">=1.2.3".into()));
+    }
+
+    #[test]
+    fn components_overflowing_are_carried_or_unbounded() {
+        assert_eq!(
+            range("=1.2.18446744073709551615"),
+            bounds("1.2.18446744073709551615", Some("1.3.0"))
+        );
+        assert_eq!(
+            range("~1.18446744073709551615"),
+            bounds("1.18446744073709551615.0", Some("2.0.0"))
+        );
+        assert_eq!(
+            range("=18446744073709551615"),
+            bounds("18446744073709551615.0.0", None)
+        );
+        assert_eq!(
+            range("^18446744073709551615.1"),
+            bounds("18446744073709551615.1.0", None)
+        );
+        assert_eq!(range(">18446744073709551615"), None);
+    }
+
+    #[test]
+    fn overlapping_requirements_are_merged_in_insertion_order() {
+        let mut set = MinimalVersionSet::default();
+        for version in ["^1", "^2", ">=1.5, <2.5"] {
+            set.insert(SharedDependency {
+                default_features: true,
+                source: DependencySource::Version(version.parse().unwrap()),
+            });
+        }
+        let sources: Vec<_> = set
+            .into_iter()
+            .map(|dep| match dep.source {
+                DependencySource::Version(version) => version.to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(sources, ["^1.5", "^2"]);
     }
-    Some(comp)
 }
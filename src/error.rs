@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+
+/// Everything that can go wrong while inheriting dependencies.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("Failed to execute `cargo metadata`. Was the command invoked inside a Rust project?")]
+    CargoMetadata(#[source] guppy::Error),
+    #[error("Failed to read `cargo metadata` output from `{path}`")]
+    ReadMetadata {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse `cargo metadata` output from `{path}`")]
+    ParseMetadata {
+        path: PathBuf,
+        #[source]
+        source: guppy::Error,
+    },
+    #[error("Failed to build package graph")]
+    PackageGraph(#[source] guppy::Error),
+    #[error("Failed to read manifest `{path}`")]
+    ReadManifest {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse manifest `{path}`")]
+    ParseManifest {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Failed to resolve the workspace root `{path}`")]
+    WorkspaceRoot {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid pattern `{pattern}` in `workspace.members`")]
+    InvalidMemberPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+    #[error("Invalid pattern `{pattern}` in `exclude-members`")]
+    InvalidExcludedMemberPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+    #[error("Invalid dependency pattern `{pattern}`")]
+    InvalidDependencyPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+    #[error("Failed to look for workspace members in `{path}`")]
+    DiscoverMembers {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("`{member}` is listed in `workspace.members`, but `{path}` doesn't exist")]
+    MissingMember { member: String, path: PathBuf },
+    #[error("Could not find `Cargo.toml` in `{path}` or any parent directory")]
+    ManifestNotFound { path: PathBuf },
+    #[error("Failed to determine the current directory")]
+    CurrentDir(#[source] std::io::Error),
+    #[error(
+        "`cargo autoinherit` can only be run in a workspace. \
+        The root manifest ({path}) does not have a `workspace` field."
+    )]
+    NotAWorkspace { path: PathBuf },
+    #[error(
+        "Expected value of `{key}` in `workspace.metadata.cargo-autoinherit` to be {expected}"
+    )]
+    InvalidMetadata {
+        key: &'static str,
+        expected: &'static str,
+    },
+    /// A manifest has a table, or a value, that isn't shaped the way Cargo expects.
+    #[error("Expected `{key}` in `{path}` to be {expected}")]
+    UnexpectedToml {
+        path: PathBuf,
+        key: String,
+        expected: &'static str,
+    },
+    #[error("Invalid `[package.metadata.cargo-autoinherit]` in `{path}`")]
+    InvalidMemberMetadata {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Invalid version requirement for `{dependency}` in `{path}`")]
+    InvalidRequirement {
+        path: PathBuf,
+        dependency: String,
+        #[source]
+        source: semver::Error,
+    },
+    /// A `path` dependency points to a directory that can't be found.
+    #[error("`{dependency}` in `{path}` points to `{dependency_path}`, which can't be accessed")]
+    MissingPathDependency {
+        path: PathBuf,
+        dependency: String,
+        dependency_path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// An entry of a [`Plan`](crate::Plan) isn't valid TOML.
+    #[error("Invalid entry `{key}` in `[{table}]` of the plan for `{path}`")]
+    InvalidPlanEntry {
+        path: PathBuf,
+        table: String,
+        key: String,
+        #[source]
+        source: Box<toml_edit::TomlError>,
+    },
+    #[error("Failed to read plan `{path}`")]
+    ReadPlan {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to write plan `{path}`")]
+    WritePlan {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A manifest was modified after the plan was computed.
+    #[error("`{path}` has changed since the plan was made. Compute a new plan.")]
+    StalePlan { path: PathBuf },
+    /// The plan modifies a manifest without recording its original contents.
+    #[error(
+        "The plan modifies `{path}`, but doesn't record its original contents. Compute a new plan."
+    )]
+    UnverifiedManifest { path: PathBuf },
+    #[error("`{path}` contains non-UTF-8 segments")]
+    NonUtf8Path { path: PathBuf },
+    /// `--on-conflict fail` was used, and some requirements couldn't be merged.
+    #[error(
+        "The version requirements of {} can't be merged. \
+        Use `--on-conflict` to pick one of them instead.",
+        quoted_list(.packages)
+    )]
+    UnresolvedConflicts { packages: Vec<String> },
+    /// `--check` was used, and some manifests would be modified.
+    #[error(
+        "{} manifest(s) would be modified. \
+        Run `cargo autoinherit` with the same options to update them.",
+        .manifests.len()
+    )]
+    CheckFailed { manifests: Vec<PathBuf> },
+    #[error("Failed to serialize report")]
+    SerializeReport(#[source] serde_json::Error),
+    #[error("Failed to write manifest `{path}`")]
+    WriteManifest {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Writing a manifest failed after others had already been replaced.
+    /// `restore_errors` is empty if all of them were restored to their original contents.
+    #[error("{}", describe_partial_write(.restore_errors))]
+    PartialWrite {
+        #[source]
+        source: Box<Error>,
+        restore_errors: Vec<Error>,
+    },
+}
+
+fn quoted_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|item| format!("`{item}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe_partial_write(restore_errors: &[Error]) -> String {
+    if restore_errors.is_empty() {
+        return "All manifests have been restored to their original contents".to_owned();
+    }
+    let errors = restore_errors
+        .iter()
+        .map(|e| match std::error::Error::source(e) {
+            Some(source) => format!("{e}: {source}"),
+            None => e.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!("Failed to restore the manifests that were already modified: {errors}")
+}
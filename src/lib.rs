@@ -1,314 +1,1027 @@
 use crate::dedup::MinimalVersionSet;
-use anyhow::{anyhow, Context};
+use crate::lints::MemberLints;
+use crate::members::{Member, MemberConfig, WorkspaceMembers};
+use crate::package_fields::MemberPackage;
+use crate::plan::ManifestEdit;
+use crate::report::{DependencyKind, MemberReport, Outcome, Report};
+use crate::root_sections::MemberSections;
 use cargo_manifest::{Dependency, DependencyDetail, DepsSet, Manifest, Workspace};
+use clap::ValueEnum;
 use guppy::VersionReq;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Formatter;
+use std::path::{Component, Path, PathBuf};
 use toml_edit::{Array, Key};
 
+pub use error::Error;
+pub use plan::{EntryChange, ManifestPlan, Plan, SkippedDependency};
+pub use report::SkipReason;
+
 mod dedup;
+mod error;
+mod lints;
+mod members;
+mod package_fields;
+mod plan;
+mod report;
+mod root_sections;
+mod transaction;
 
 #[derive(Debug, Default, Clone, clap::Args)]
 pub struct AutoInheritConf {
+    /// Path to the root `Cargo.toml` of the workspace.
+    /// Defaults to the workspace that contains the current directory.
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+    /// Find the workspace members from `workspace.members` and `workspace.exclude` in the root
+    /// manifest, instead of running `cargo metadata`, which resolves the whole dependency graph.
+    #[arg(long, visible_alias = "offline", conflicts_with = "merge_strategy")]
+    pub no_resolve: bool,
+    /// Read the output of `cargo metadata --format-version 1` from a file,
+    /// instead of running it.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["manifest_path", "no_resolve"]
+    )]
+    pub metadata_path: Option<PathBuf>,
     #[arg(
         long,
         help = "Represents inherited dependencies as `package.workspace = true` if possible."
     )]
     pub prefer_simple_dotted: bool,
-    /// Package name(s) of workspace member(s) to exclude.
-    #[arg(short, long)]
-    exclude_members: Vec<String>,
+    /// Also hoist the `[package]` fields that members share, like `edition` or `license`,
+    /// into `[workspace.package]`.
+    #[arg(long)]
+    pub package_fields: bool,
+    /// Also hoist the `[lints]` tables that members share into `[workspace.lints]`.
+    #[arg(long)]
+    pub lints: bool,
+    /// Workspace member(s) to exclude, as glob pattern(s) matching either their package name
+    /// or their path relative to the workspace root, e.g. `*-fuzz` or `examples/**`.
+    #[arg(short, long, value_name = "PATTERN")]
+    pub exclude_members: Vec<String>,
+    /// Glob pattern(s) of dependencies to leave untouched, e.g. `windows-*`.
+    /// Added to `exclude-dependencies` in `workspace.metadata.cargo-autoinherit`.
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude_dependencies: Vec<String>,
+    /// Only inherit the dependencies that match these glob pattern(s), e.g. `serde*`.
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<String>,
+    /// Print the changes that would be made to each manifest, without writing them to disk.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Exit with an error, without writing anything, if any dependency could still be inherited.
+    #[arg(long)]
+    pub check: bool,
+    /// Print a unified diff of every manifest change.
+    /// Combine it with `--dry-run` or `--check` to leave the manifests untouched.
+    #[arg(long)]
+    pub diff: bool,
+    /// The format used to report what was inherited and what was skipped.
+    #[arg(long, value_enum, default_value_t, conflicts_with = "diff")]
+    pub message_format: MessageFormat,
+    /// How the version requirements of a dependency are merged into the inherited one.
+    #[arg(long, value_enum, default_value_t)]
+    pub merge_strategy: MergeStrategy,
+    /// What to do when the version requirements of a dependency can't be merged.
+    /// Defaults to `skip`, unless `on-conflict` is set in `workspace.metadata.cargo-autoinherit`.
+    #[arg(long, value_enum)]
+    pub on_conflict: Option<OnConflict>,
+    /// Only inherit dependencies used by at least this many members.
+    /// Defaults to 1, unless `min-members` is set in `workspace.metadata.cargo-autoinherit`.
+    /// Dependencies that are already in `[workspace.dependencies]` are always inherited.
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub min_members: Option<usize>,
+    /// Write the inheritance plan to a JSON file, without modifying any manifest.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["check", "dry_run", "diff"])]
+    pub emit_plan: Option<PathBuf>,
+    /// Apply a plan written by `--emit-plan`.
+    /// Fails if any manifest has changed since the plan was made.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "emit_plan",
+            "manifest_path",
+            "no_resolve",
+            "metadata_path",
+            "exclude_members",
+            "exclude_dependencies",
+            "only",
+            "message_format",
+            "merge_strategy",
+            "on_conflict",
+            "min_members",
+            "prefer_simple_dotted",
+            "package_fields",
+            "lints",
+        ]
+    )]
+    pub apply_plan: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeStrategy {
+    /// Use the intersection of all the version requirements.
+    #[default]
+    Intersection,
+    /// Use the intersection of all the version requirements, starting from the version
+    /// resolved in `Cargo.lock`, so that inheriting doesn't change what gets built.
+    Lockfile,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Human-readable messages.
+    #[default]
+    Human,
+    /// A single JSON document, printed to stdout.
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// Leave the dependency untouched.
+    #[default]
+    Skip,
+    /// Inherit the highest requirement, even across major versions.
+    Highest,
+    /// Inherit the lowest requirement, even across major versions.
+    Lowest,
+    /// Exit with an error, without modifying any manifest.
+    Fail,
 }
 
 #[derive(Debug, Default)]
 struct AutoInheritMetadata {
     exclude_members: Vec<String>,
+    exclude_dependencies: Vec<String>,
+    on_conflict: Option<OnConflict>,
+    min_members: Option<usize>,
 }
 
 impl AutoInheritMetadata {
-    fn from_workspace(workspace: &Workspace<toml::Table>) -> Result<Self, anyhow::Error> {
-        fn error() -> anyhow::Error {
-            anyhow!("Excpected value of `exclude` in `workspace.metadata.cargo-autoinherit` to be an array of strings")
-        }
-
-        let Some(exclude) = workspace
+    fn from_workspace(workspace: &Workspace<toml::Table>) -> Result<Self, Error> {
+        let Some(metadata) = workspace
             .metadata
             .as_ref()
             .and_then(|m| m.get("cargo-autoinherit"))
             .and_then(|v| v.as_table())
-            .and_then(|t| t.get("exclude-members").or(t.get("exclude_members")))
         else {
             return Ok(Self::default());
         };
 
-        let exclude: Vec<String> = match exclude {
-            toml::Value::Array(excluded) => excluded
-                .iter()
-                .map(|v| v.as_str().ok_or_else(error).map(|s| s.to_string()))
-                .try_fold(Vec::with_capacity(excluded.len()), |mut res, item| {
-                    res.push(item?);
-                    Ok::<_, anyhow::Error>(res)
-                })?,
-            _ => return Err(error()),
-        };
+        let exclude = string_array(metadata, "exclude-members")?;
+        let exclude_dependencies = string_array(metadata, "exclude-dependencies")?;
+        let on_conflict = metadata
+            .get("on-conflict")
+            .or(metadata.get("on_conflict"))
+            .map(|value| {
+                value
+                    .as_str()
+                    .and_then(|s| OnConflict::from_str(s, false).ok())
+                    .ok_or(Error::InvalidMetadata {
+                        key: "on-conflict",
+                        expected: "one of `skip`, `highest`, `lowest` or `fail`",
+                    })
+            })
+            .transpose()?;
+        let min_members = metadata
+            .get("min-members")
+            .or(metadata.get("min_members"))
+            .map(|value| {
+                value
+                    .as_integer()
+                    .filter(|n| *n >= 1)
+                    .and_then(|n| usize::try_from(n).ok())
+                    .ok_or(Error::InvalidMetadata {
+                        key: "min-members",
+                        expected: "a positive integer",
+                    })
+            })
+            .transpose()?;
         Ok(Self {
             exclude_members: exclude,
+            exclude_dependencies,
+            on_conflict,
+            min_members,
         })
     }
 }
 
-/// Rewrites a `path` dependency as being absolute, based on a given path
-fn rewrite_dep_paths_as_absolute<'a, P: AsRef<std::path::Path>>(
-    deps: impl Iterator<Item = &'a mut Dependency>,
-    parent: P,
-) {
-    deps.for_each(|dep| {
-        if let Dependency::Detailed(detail) = dep {
-            detail.path = detail.path.as_mut().map(|path| {
-                parent
-                    .as_ref()
-                    .join(path)
-                    .canonicalize()
-                    .unwrap()
-                    .to_str()
-                    .expect("Canonicalized absolute path contained non-UTF-8 segments.")
-                    .to_string()
-            })
-        }
-    });
+/// Reads `key`, or its `snake_case` spelling, from the metadata as an array of strings.
+fn string_array(metadata: &toml::Table, key: &'static str) -> Result<Vec<String>, Error> {
+    let error = || Error::InvalidMetadata {
+        key,
+        expected: "an array of strings",
+    };
+    match metadata.get(key).or(metadata.get(&key.replace('-', "_"))) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::Array(values)) => values
+            .iter()
+            .map(|v| v.as_str().map(ToOwned::to_owned).ok_or_else(error))
+            .collect(),
+        Some(_) => Err(error()),
+    }
 }
 
-/// Rewrites a `path` dependency as being relative, based on a given path
-fn rewrite_dep_path_as_relative<P: AsRef<std::path::Path>>(dep: &mut Dependency, parent: P) {
+fn glob_patterns<'a>(
+    patterns: impl IntoIterator<Item = &'a String>,
+    error: fn(String, glob::PatternError) -> Error,
+) -> Result<Vec<glob::Pattern>, Error> {
+    patterns
+        .into_iter()
+        .map(|pattern| glob::Pattern::new(pattern).map_err(|e| error(pattern.clone(), e)))
+        .collect()
+}
+
+/// Whether `member` matches one of `patterns`, by package name or by its path
+/// relative to the workspace root.
+fn is_excluded_member(member: &Member, workspace_root: &Path, patterns: &[glob::Pattern]) -> bool {
+    let member_dir = member.manifest_path.parent().unwrap_or(Path::new(""));
+    let relative_dir = member_dir
+        .strip_prefix(workspace_root)
+        .unwrap_or(member_dir);
+    // `examples/*` shouldn't match members nested deeper, like `examples/foo/bar`.
+    let path_options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    patterns.iter().any(|pattern| {
+        pattern.matches(&member.name) || pattern.matches_path_with(relative_dir, path_options)
+    })
+}
+
+/// Rewrites the `path` dependencies declared in `manifest_path` as being absolute.
+fn rewrite_dep_paths_as_absolute(deps: &mut DepsSet, manifest_path: &Path) -> Result<(), Error> {
+    let parent = manifest_path.parent().unwrap_or(Path::new(""));
+    for (name, dep) in deps.iter_mut() {
+        let Dependency::Detailed(detail) = dep else {
+            continue;
+        };
+        let Some(path) = &mut detail.path else {
+            continue;
+        };
+        let dependency_path = parent.join(&*path);
+        let absolute =
+            dependency_path
+                .canonicalize()
+                .map_err(|e| Error::MissingPathDependency {
+                    path: manifest_path.to_owned(),
+                    dependency: name.clone(),
+                    dependency_path: dependency_path.clone(),
+                    source: e,
+                })?;
+        *path = path_to_string(absolute)?;
+    }
+    Ok(())
+}
+
+/// Rewrites a `path` dependency as being relative, based on a given canonical path
+fn rewrite_dep_path_as_relative(dep: &mut Dependency, parent: &Path) -> Result<(), Error> {
     if let Dependency::Detailed(detail) = dep {
-        detail.path = detail.path.as_mut().map(|path| {
-            pathdiff::diff_paths(path, parent.as_ref().canonicalize().unwrap())
-                .expect(
-                    "Error rewriting dependency path as relative: unable to determine path diff.",
-                )
-                .to_str()
-                .expect("Error rewriting dependency path as relative: path diff is not UTF-8.")
-                .to_string()
-        })
+        if let Some(path) = &mut detail.path {
+            // A relative path can't be rewritten, it's kept as-is.
+            if let Some(relative) = pathdiff::diff_paths(&*path, parent) {
+                *path = path_to_string(relative)?;
+            }
+        }
     }
+    Ok(())
 }
 
-// Gets the first entry out of the document as a table if it exists,
-// or gets the second one if it doesn't. If that doesn't exist
-// either, then it returns an error.
-// Borrowing rules make it hard to do this in a function,
-// so here we are.
-macro_rules! get_either_table_mut {
-    ($first:literal, $second:literal, $manifest_toml:expr) => {
-        if let Some(i) = $manifest_toml
-            .get_mut($first)
-            .and_then(|d| d.as_table_mut())
-        {
-            Ok(i)
-        } else if let Some(i) = $manifest_toml
-            .get_mut($second)
-            .and_then(|d| d.as_table_mut())
+fn path_to_string(path: PathBuf) -> Result<String, Error> {
+    path.into_os_string()
+        .into_string()
+        .map_err(|path| Error::NonUtf8Path { path: path.into() })
+}
+
+/// Returns the value that appears the most in `values`,
+/// as long as it appears at least twice and no other value appears as many times.
+fn most_common<'a, T: PartialEq>(values: impl IntoIterator<Item = &'a T>) -> Option<&'a T> {
+    let mut counts: Vec<(&T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(other, _)| *other == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    match counts.as_slice() {
+        [(value, count), rest @ ..]
+            if *count > 1 && rest.first().is_none_or(|(_, other)| other != count) =>
         {
-            Ok(i)
-        } else {
-            Err(anyhow::anyhow!(concat!(
-                "Failed to find `[",
-                $first,
-                "]` table in root manifest."
-            )))
+            Some(*value)
+        }
+        _ => None,
+    }
+}
+
+/// Rewrites `path`, relative to `manifest_dir`, as relative to `workspace_root`.
+///
+/// `.` and `..` are resolved without touching the filesystem, since `path` may not exist.
+fn rebase_path(path: &str, manifest_dir: &Path, workspace_root: &Path) -> Result<String, Error> {
+    let mut absolute = PathBuf::new();
+    for component in manifest_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+    path_to_string(pathdiff::diff_paths(&absolute, workspace_root).unwrap_or(absolute))
+}
+
+/// Gets the first entry out of `table` as a table if it exists,
+/// or gets the second one if it doesn't, along with the key it was found under.
+/// If that doesn't exist either, then it returns an error.
+fn get_either_table<'a>(
+    table: &'a toml_edit::Table,
+    first: &'static str,
+    second: &'static str,
+    manifest_path: &Path,
+) -> Result<(&'static str, &'a toml_edit::Table), Error> {
+    [first, second]
+        .into_iter()
+        .find_map(|key| Some((key, table.get(key)?.as_table()?)))
+        .ok_or_else(|| Error::UnexpectedToml {
+            path: manifest_path.to_owned(),
+            key: first.to_owned(),
+            expected: "a table",
+        })
+}
+
+/// Runs `cargo autoinherit`: works out which dependencies can be inherited,
+/// reports it, and then writes, prints or checks the resulting manifests according to `conf`.
+pub fn auto_inherit(conf: AutoInheritConf) -> Result<(), Error> {
+    let plan = match &conf.apply_plan {
+        Some(path) => Plan::read(path)?,
+        None => {
+            let mut report = Report::default();
+            let plan = match plan_with_report(&conf, &mut report) {
+                Err(e @ Error::UnresolvedConflicts { .. }) => {
+                    print_report(&report, conf.message_format)?;
+                    return Err(e);
+                }
+                result => result?,
+            };
+            report.manifests = plan.manifests().cloned().collect();
+            print_report(&report, conf.message_format)?;
+            if let Some(path) = &conf.emit_plan {
+                return plan.write(path);
+            }
+            plan
         }
     };
+    let edits = plan.edit_manifests()?;
+
+    if conf.diff {
+        let workspace_root = plan.root.path.parent().unwrap_or(Path::new(""));
+        print_diff(&edits, workspace_root);
+    }
+    if conf.check {
+        return check(&edits);
+    }
+    if conf.dry_run {
+        if !conf.diff && conf.message_format == MessageFormat::Human {
+            print_dry_run(&edits);
+        }
+        return Ok(());
+    }
+    transaction::write_manifests(&edits)
 }
 
-pub fn auto_inherit(conf: AutoInheritConf) -> Result<(), anyhow::Error> {
-    let metadata = guppy::MetadataCommand::new().exec().context(
-        "Failed to execute `cargo metadata`. Was the command invoked inside a Rust project?",
-    )?;
-    let graph = metadata
-        .build_graph()
-        .context("Failed to build package graph")?;
-    let workspace_root = graph.workspace().root();
-    let mut root_manifest: Manifest<toml::Value, toml::Table> = {
-        let contents = fs_err::read_to_string(workspace_root.join("Cargo.toml").as_std_path())
-            .context("Failed to read root manifest")?;
-        toml::from_str(&contents).context("Failed to parse root manifest")?
+/// Works out which dependencies can be inherited, without modifying any manifest.
+///
+/// The returned [`Plan`] can be inspected or filtered, and then passed to [`apply`].
+pub fn plan(conf: &AutoInheritConf) -> Result<Plan, Error> {
+    plan_with_report(conf, &mut Report::default())
+}
+
+/// Writes the changes described by `plan` to the manifests.
+///
+/// Every manifest is read again and only the entries in the plan are rewritten.
+/// Either all manifests are written, or none of them is.
+pub fn apply(plan: &Plan) -> Result<(), Error> {
+    transaction::write_manifests(&plan.edit_manifests()?)
+}
+
+fn plan_with_report(conf: &AutoInheritConf, report: &mut Report) -> Result<Plan, Error> {
+    let workspace_members = if let Some(metadata_path) = &conf.metadata_path {
+        WorkspaceMembers::from_metadata_file(metadata_path)?
+    } else if conf.no_resolve {
+        WorkspaceMembers::from_root_manifest(conf.manifest_path.as_deref())?
+    } else {
+        WorkspaceMembers::from_cargo_metadata(conf.manifest_path.as_deref())?
     };
+    let workspace_root = workspace_members.root.as_path();
+    let root_manifest_path = workspace_root.join("Cargo.toml");
+    let root_contents = read_manifest(&root_manifest_path)?;
+    let mut root_manifest: Manifest<toml::Value, toml::Table> =
+        parse_manifest(&root_manifest_path, &root_contents)?;
+    let raw_root_manifest: toml::Table = parse_manifest(&root_manifest_path, &root_contents)?;
+    let root_document = parse_document(&root_manifest_path, &root_contents)?;
+    let workspace_deps = root_document
+        .get("workspace")
+        .and_then(|w| w.get("dependencies"))
+        .and_then(|d| d.as_table_like());
+    let mut originals = BTreeMap::from([(root_manifest_path.clone(), root_contents)]);
     let Some(workspace) = &mut root_manifest.workspace else {
-        anyhow::bail!(
-            "`cargo autoinherit` can only be run in a workspace. \
-            The root manifest ({}) does not have a `workspace` field.",
-            workspace_root
-        )
+        return Err(Error::NotAWorkspace {
+            path: root_manifest_path,
+        });
     };
 
     let autoinherit_metadata = AutoInheritMetadata::from_workspace(workspace)?;
-    let excluded_members = BTreeSet::from_iter(
+    let on_conflict = conf
+        .on_conflict
+        .or(autoinherit_metadata.on_conflict)
+        .unwrap_or_default();
+    let min_members = conf
+        .min_members
+        .or(autoinherit_metadata.min_members)
+        .unwrap_or(1);
+    let dependency_pattern_error =
+        |pattern, source| Error::InvalidDependencyPattern { pattern, source };
+    let excluded_dependencies = glob_patterns(
+        conf.exclude_dependencies
+            .iter()
+            .chain(&autoinherit_metadata.exclude_dependencies),
+        dependency_pattern_error,
+    )?;
+    let only_dependencies = glob_patterns(&conf.only, dependency_pattern_error)?;
+    let excluded_member_patterns = glob_patterns(
         conf.exclude_members
-            .into_iter()
-            .chain(autoinherit_metadata.exclude_members),
-    );
+            .iter()
+            .chain(&autoinherit_metadata.exclude_members),
+        |pattern, source| Error::InvalidExcludedMemberPattern { pattern, source },
+    )?;
+    let excluded_members: BTreeSet<&str> = workspace_members
+        .members
+        .iter()
+        .filter(|member| {
+            member.config.skip
+                || is_excluded_member(member, workspace_root, &excluded_member_patterns)
+        })
+        .map(|member| member.name.as_str())
+        .collect();
 
+    // The versions resolved in `Cargo.lock` for the direct dependencies of workspace members.
+    let mut package_name2locked_versions: BTreeMap<String, BTreeSet<guppy::Version>> =
+        BTreeMap::new();
     let mut package_name2specs: BTreeMap<String, Action> = BTreeMap::new();
+    // The keys each package is declared under—e.g. `json` for `json = { package = "serde_json" }`.
+    let mut package_name2aliases: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    // The keys we can't carry over to an inherited dependency, for each package.
+    let mut package_name2unsupported_keys: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    // The members that depend on each package.
+    let mut package_name2members: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut member_packages = Vec::new();
+    let mut member_lints = Vec::new();
+    let mut member_sections = Vec::new();
     if let Some(deps) = &mut workspace.dependencies {
-        rewrite_dep_paths_as_absolute(deps.values_mut(), workspace_root);
-        process_deps(deps, &mut package_name2specs);
+        rewrite_dep_paths_as_absolute(deps, &root_manifest_path)?;
+        process_deps(
+            deps,
+            &root_manifest_path,
+            &mut package_name2specs,
+            &mut package_name2aliases,
+        )?;
+    }
+    if let Some(deps) = raw_root_manifest
+        .get("workspace")
+        .and_then(|w| w.get("dependencies"))
+        .and_then(|d| d.as_table())
+    {
+        collect_unsupported_keys(deps, &mut package_name2unsupported_keys);
     }
 
-    for member_id in graph.workspace().member_ids() {
-        let package = graph.metadata(member_id)?;
-        assert!(package.in_workspace());
+    for member in &workspace_members.members {
+        let excluded = excluded_members.contains(member.name.as_str());
+        report.members.push(MemberReport {
+            name: member.name.clone(),
+            manifest_path: member.manifest_path.clone(),
+            skipped: excluded.then_some(SkipReason::ExcludedMember),
+        });
 
-        let mut manifest: Manifest = {
-            if excluded_members.contains(package.name()) {
-                println!("Excluded workspace member `{}`", package.name());
-                continue;
+        if excluded {
+            continue;
+        }
+        let manifest_path = member.manifest_path.as_path();
+        let contents = read_manifest(manifest_path)?;
+        let mut manifest: Manifest = parse_manifest(manifest_path, &contents)?;
+        let mut raw_manifest: toml::Table = parse_manifest(manifest_path, &contents)?;
+        remove_skipped_deps(&mut manifest, &member.config);
+        remove_skipped_raw_deps(&mut raw_manifest, &member.config);
+        originals.insert(manifest_path.to_owned(), contents);
+        if conf.package_fields {
+            if let Some(package) = raw_manifest.get("package").and_then(|p| p.as_table()) {
+                member_packages.push(MemberPackage {
+                    name: member.name.clone(),
+                    manifest_path: manifest_path.to_owned(),
+                    package: package.clone(),
+                });
             }
-            let contents = fs_err::read_to_string(package.manifest_path().as_std_path())
-                .context("Failed to read root manifest")?;
-            toml::from_str(&contents).context("Failed to parse root manifest")?
-        };
+        }
+        // Only the root package's own sections are taken into account by Cargo.
+        if manifest_path != root_manifest_path {
+            member_sections.extend(MemberSections::new(
+                &member.name,
+                manifest_path,
+                &raw_manifest,
+            ));
+        }
+        if conf.lints {
+            if let Some(lints) = raw_manifest.get("lints").and_then(|l| l.as_table()) {
+                member_lints.push(MemberLints {
+                    name: member.name.clone(),
+                    manifest_path: manifest_path.to_owned(),
+                    lints: lints.clone(),
+                });
+            }
+        }
+        for deps in member_dependency_tables(&raw_manifest) {
+            collect_unsupported_keys(deps, &mut package_name2unsupported_keys);
+            for (name, dep) in deps {
+                let package_name = dep.get("package").and_then(|p| p.as_str()).unwrap_or(name);
+                package_name2members
+                    .entry(package_name.to_owned())
+                    .or_default()
+                    .insert(member.name.clone());
+            }
+        }
+        for (package_name, version) in &member.locked_versions {
+            package_name2locked_versions
+                .entry(package_name.clone())
+                .or_default()
+                .insert(version.clone());
+        }
         if let Some(deps) = &mut manifest.dependencies {
-            rewrite_dep_paths_as_absolute(
-                deps.values_mut(),
-                package.manifest_path().parent().unwrap(),
-            );
-            process_deps(deps, &mut package_name2specs);
+            rewrite_dep_paths_as_absolute(deps, manifest_path)?;
+            process_deps(
+                deps,
+                manifest_path,
+                &mut package_name2specs,
+                &mut package_name2aliases,
+            )?;
+            report.record_usages(deps, &member.name, DependencyKind::Normal);
         }
         if let Some(deps) = &mut manifest.dev_dependencies {
-            rewrite_dep_paths_as_absolute(
-                deps.values_mut(),
-                package.manifest_path().parent().unwrap(),
-            );
-            process_deps(deps, &mut package_name2specs);
+            rewrite_dep_paths_as_absolute(deps, manifest_path)?;
+            process_deps(
+                deps,
+                manifest_path,
+                &mut package_name2specs,
+                &mut package_name2aliases,
+            )?;
+            report.record_usages(deps, &member.name, DependencyKind::Dev);
         }
         if let Some(deps) = &mut manifest.build_dependencies {
-            rewrite_dep_paths_as_absolute(
-                deps.values_mut(),
-                package.manifest_path().parent().unwrap(),
-            );
-            process_deps(deps, &mut package_name2specs);
+            rewrite_dep_paths_as_absolute(deps, manifest_path)?;
+            process_deps(
+                deps,
+                manifest_path,
+                &mut package_name2specs,
+                &mut package_name2aliases,
+            )?;
+            report.record_usages(deps, &member.name, DependencyKind::Build);
+        }
+        if let Some(targets) = &mut manifest.target {
+            for (target, target_deps) in targets.iter_mut() {
+                for (deps, kind) in [
+                    (&mut target_deps.dependencies, DependencyKind::Normal),
+                    (&mut target_deps.dev_dependencies, DependencyKind::Dev),
+                    (&mut target_deps.build_dependencies, DependencyKind::Build),
+                ] {
+                    rewrite_dep_paths_as_absolute(deps, manifest_path)?;
+                    process_deps(
+                        deps,
+                        manifest_path,
+                        &mut package_name2specs,
+                        &mut package_name2aliases,
+                    )?;
+                    report.record_target_usages(deps, &member.name, kind, target);
+                }
+            }
+        }
+    }
+
+    // A key in `[workspace.dependencies]` can only point to a single package.
+    let mut alias2package_names: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (package_name, aliases) in &package_name2aliases {
+        for alias in aliases {
+            alias2package_names
+                .entry(alias)
+                .or_default()
+                .insert(package_name);
         }
     }
 
     let mut package_name2inherited_source: BTreeMap<String, SharedDependency> = BTreeMap::new();
+    let mut conflicting_package_names = Vec::new();
+    // Packages whose conflicting requirements were resolved according to `--on-conflict`.
+    // Their existing `[workspace.dependencies]` entry, if any, must be overwritten.
+    let mut resolved_package_names = BTreeSet::new();
+    let workspace_package_names: BTreeSet<String> = workspace
+        .dependencies
+        .iter()
+        .flatten()
+        .map(|(name, dep)| dep.package().unwrap_or(name).to_owned())
+        .collect();
     'outer: for (package_name, action) in package_name2specs {
-        let Action::TryInherit(specs) = action else {
-            eprintln!("`{package_name}` won't be auto-inherited because it appears at least once from a source type \
-                that we currently don't support (e.g. private registry, path dependency).");
+        // Patterns match the package name, as well as the keys it's declared under.
+        let names: Vec<&str> = std::iter::once(package_name.as_str())
+            .chain(
+                package_name2aliases
+                    .get(&package_name)
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str),
+            )
+            .collect();
+        let matches = |patterns: &[glob::Pattern]| {
+            patterns
+                .iter()
+                .any(|pattern| names.iter().any(|name| pattern.matches(name)))
+        };
+        if matches(&excluded_dependencies)
+            || (!only_dependencies.is_empty() && !matches(&only_dependencies))
+        {
+            report.skipped(&package_name, SkipReason::ExcludedByConfig);
+            continue;
+        }
+        let members = package_name2members
+            .get(&package_name)
+            .map_or(0, BTreeSet::len);
+        if members < min_members && !workspace_package_names.contains(&package_name) {
+            report.skipped(&package_name, SkipReason::UsedByFewMembers { min_members });
+            continue;
+        }
+        let Action::TryInherit(mut specs) = action else {
+            report.skipped(&package_name, SkipReason::UnsupportedSource);
             continue;
         };
+        if conf.merge_strategy == MergeStrategy::Lockfile {
+            // If members resolve to different versions, we can't pick one without changing the build.
+            if let Some(locked_versions) = package_name2locked_versions
+                .get(&package_name)
+                .filter(|versions| versions.len() == 1)
+            {
+                specs.merge_into_locked(locked_versions.first().unwrap());
+            }
+        }
+        if let Some(keys) = package_name2unsupported_keys.get(&package_name) {
+            let keys = keys.iter().cloned().collect();
+            report.skipped(&package_name, SkipReason::UnsupportedKeys { keys });
+            continue;
+        }
+        for alias in package_name2aliases
+            .get(&package_name)
+            .into_iter()
+            .flatten()
+        {
+            let package_names = &alias2package_names[alias.as_str()];
+            if package_names.len() > 1 {
+                let reason = SkipReason::AmbiguousAlias {
+                    alias: alias.clone(),
+                    packages: package_names.iter().map(|p| p.to_string()).collect(),
+                };
+                report.skipped(&package_name, reason);
+                continue 'outer;
+            }
+        }
         if specs.len() > 1 {
-            eprintln!("`{package_name}` won't be auto-inherited because there are multiple sources for it:");
-            for spec in specs.into_iter() {
-                eprintln!("  - {}", spec.source);
+            let picked = match on_conflict {
+                OnConflict::Skip => None,
+                OnConflict::Highest => specs.pick(true),
+                OnConflict::Lowest => specs.pick(false),
+                OnConflict::Fail => {
+                    conflicting_package_names.push(package_name.clone());
+                    None
+                }
+            };
+            // The existing entries in `[workspace.dependencies]` must be updated in place.
+            let picked = picked.filter(|spec| {
+                package_name2aliases[&package_name].iter().all(|alias| {
+                    workspace_deps
+                        .and_then(|deps| deps.get(alias))
+                        .is_none_or(|existing| {
+                            existing.is_str()
+                                || update_workspace_dep(alias, existing, &spec.source).is_some()
+                        })
+                })
+            });
+            let disjoint = specs.has_disjoint_requirements();
+            let mut sources: Vec<_> = specs.into_iter().collect();
+            sources.sort_by_cached_key(|spec| spec.source.to_string());
+            if let Some(spec) = picked {
+                report.resolved_conflict(&package_name, spec.clone(), on_conflict, sources);
+                resolved_package_names.insert(package_name.clone());
+                package_name2inherited_source.insert(package_name, spec);
+                continue 'outer;
             }
+            let reason = if disjoint {
+                SkipReason::IncompatibleVersions { sources }
+            } else {
+                SkipReason::MultipleSources { sources }
+            };
+            report.skipped(&package_name, reason);
             continue 'outer;
         }
 
         let spec = specs.into_iter().next().unwrap();
+        report.inherited(&package_name, spec.clone());
         package_name2inherited_source.insert(package_name, spec);
     }
+    if !conflicting_package_names.is_empty() {
+        return Err(Error::UnresolvedConflicts {
+            packages: conflicting_package_names,
+        });
+    }
 
     // Add new "shared" dependencies to `[workspace.dependencies]`
-    let mut workspace_toml: toml_edit::DocumentMut = {
-        let contents = fs_err::read_to_string(workspace_root.join("Cargo.toml").as_std_path())
-            .context("Failed to read root manifest")?;
-        contents.parse().context("Failed to parse root manifest")?
-    };
-    let workspace_table = workspace_toml.as_table_mut()["workspace"]
-        .as_table_mut()
-        .expect(
-            "Failed to find `[workspace]` table in root manifest. \
-        This is a bug in `cargo_autoinherit`.",
-        );
-    let workspace_deps = workspace_table
-        .entry("dependencies")
-        .or_insert(toml_edit::Item::Table(toml_edit::Table::new()))
-        .as_table_mut()
-        .expect("Failed to find `[workspace.dependencies]` table in root manifest.");
-    let mut was_modified = false;
+    let canonical_workspace_root =
+        workspace_root
+            .canonicalize()
+            .map_err(|e| Error::WorkspaceRoot {
+                path: workspace_root.to_owned(),
+                source: e,
+            })?;
+    let mut changes = Vec::new();
     for (package_name, source) in &package_name2inherited_source {
-        if workspace_deps.get(package_name).is_some() {
-            continue;
-        } else {
-            let mut dep = shared2dep(source);
-            rewrite_dep_path_as_relative(&mut dep, workspace_root);
+        for alias in &package_name2aliases[package_name] {
+            let existing = workspace_deps.and_then(|deps| deps.get(alias));
+            if existing.is_some() && !resolved_package_names.contains(package_name) {
+                continue;
+            } else if let Some(existing) = existing.filter(|existing| !existing.is_str()) {
+                changes.extend(update_workspace_dep(alias, existing, &source.source));
+            } else {
+                let package = (alias != package_name).then_some(package_name.as_str());
+                let mut dep = shared2dep(source, package);
+                rewrite_dep_path_as_relative(&mut dep, &canonical_workspace_root)?;
 
-            insert_preserving_decor(workspace_deps, package_name, dep2toml_item(&dep));
-            was_modified = true;
+                let item = dep2toml_item(&dep);
+                changes.push(EntryChange::new("workspace.dependencies", alias, &item));
+            }
         }
     }
-    if was_modified {
-        fs_err::write(
-            workspace_root.join("Cargo.toml").as_std_path(),
-            workspace_toml.to_string(),
-        )
-        .context("Failed to write manifest")?;
+    let mut member2changes: BTreeMap<PathBuf, Vec<EntryChange>> = BTreeMap::new();
+    if conf.package_fields {
+        let workspace_package = raw_root_manifest
+            .get("workspace")
+            .and_then(|w| w.get("package"))
+            .and_then(|p| p.as_table());
+        let member2package_changes = package_fields::inherit_package_fields(
+            workspace_root,
+            workspace_package,
+            &member_packages,
+            report,
+            &mut changes,
+        )?;
+        merge_member_changes(&mut member2changes, member2package_changes);
     }
+    if conf.lints {
+        let workspace_lints = raw_root_manifest
+            .get("workspace")
+            .and_then(|w| w.get("lints"))
+            .and_then(|l| l.as_table());
+        let member2lints_changes =
+            lints::inherit_lints(workspace_lints, &member_lints, report, &mut changes);
+        merge_member_changes(&mut member2changes, member2lints_changes);
+    }
+    let member2section_changes = root_sections::move_root_sections(
+        workspace_root,
+        &raw_root_manifest,
+        &member_sections,
+        report,
+        &mut changes,
+    )?;
+    merge_member_changes(&mut member2changes, member2section_changes);
+    let mut root = ManifestPlan {
+        path: root_manifest_path,
+        changes,
+    };
 
     // Inherit new "shared" dependencies in each member's manifest
-    for member_id in graph.workspace().member_ids() {
-        let package = graph.metadata(member_id)?;
-        if excluded_members.contains(package.name()) {
+    let mut members = Vec::new();
+    for member in &workspace_members.members {
+        if excluded_members.contains(member.name.as_str()) {
             continue;
         }
 
-        let manifest_contents = fs_err::read_to_string(package.manifest_path().as_std_path())
-            .context("Failed to read root manifest")?;
-        let manifest: Manifest =
-            toml::from_str(&manifest_contents).context("Failed to parse root manifest")?;
-        let mut manifest_toml: toml_edit::DocumentMut = manifest_contents
-            .parse()
-            .context("Failed to parse root manifest")?;
-        let mut was_modified = false;
+        let manifest_path = member.manifest_path.as_path();
+        let manifest_contents = read_manifest(manifest_path)?;
+        let mut manifest: Manifest = parse_manifest(manifest_path, &manifest_contents)?;
+        remove_skipped_deps(&mut manifest, &member.config);
+        let manifest_toml = parse_document(manifest_path, &manifest_contents)?;
+        let mut changes = member2changes.remove(manifest_path).unwrap_or_default();
         if let Some(deps) = &manifest.dependencies {
-            let deps_toml = manifest_toml["dependencies"]
-                .as_table_mut()
-                .expect("Failed to find `[dependencies]` table in root manifest.");
+            let (table_name, deps_toml) = get_either_table(
+                &manifest_toml,
+                "dependencies",
+                "dependencies",
+                manifest_path,
+            )?;
             inherit_deps(
                 deps,
                 deps_toml,
+                table_name,
                 &package_name2inherited_source,
-                &mut was_modified,
-                conf.prefer_simple_dotted,
+                &mut changes,
             );
         }
         if let Some(deps) = &manifest.dev_dependencies {
-            let deps_toml =
-                get_either_table_mut!("dev-dependencies", "dev_dependencies", manifest_toml)?;
-
+            let (table_name, deps_toml) = get_either_table(
+                &manifest_toml,
+                "dev-dependencies",
+                "dev_dependencies",
+                manifest_path,
+            )?;
             inherit_deps(
                 deps,
                 deps_toml,
+                table_name,
                 &package_name2inherited_source,
-                &mut was_modified,
-                conf.prefer_simple_dotted,
+                &mut changes,
             );
         }
         if let Some(deps) = &manifest.build_dependencies {
-            let deps_toml =
-                get_either_table_mut!("build-dependencies", "build_dependencies", manifest_toml)?;
-
+            let (table_name, deps_toml) = get_either_table(
+                &manifest_toml,
+                "build-dependencies",
+                "build_dependencies",
+                manifest_path,
+            )?;
             inherit_deps(
                 deps,
                 deps_toml,
+                table_name,
                 &package_name2inherited_source,
-                &mut was_modified,
-                conf.prefer_simple_dotted,
+                &mut changes,
             );
         }
-        if was_modified {
-            fs_err::write(
-                package.manifest_path().as_std_path(),
-                manifest_toml.to_string(),
-            )
-            .context("Failed to write manifest")?;
+        if let Some(targets) = &manifest.target {
+            for (target, target_deps) in targets {
+                let target_toml = manifest_toml
+                    .get("target")
+                    .and_then(|t| t.get(target.as_str()))
+                    .and_then(|t| t.as_table())
+                    .ok_or_else(|| Error::UnexpectedToml {
+                        path: manifest_path.to_owned(),
+                        key: format!("target.{}", Key::new(target.as_str())),
+                        expected: "a table",
+                    })?;
+                let target_key = Key::new(target.as_str());
+                for (deps, first, second) in [
+                    (&target_deps.dependencies, "dependencies", "dependencies"),
+                    (
+                        &target_deps.dev_dependencies,
+                        "dev-dependencies",
+                        "dev_dependencies",
+                    ),
+                    (
+                        &target_deps.build_dependencies,
+                        "build-dependencies",
+                        "build_dependencies",
+                    ),
+                ] {
+                    if deps.is_empty() {
+                        continue;
+                    }
+                    let (table_name, deps_toml) =
+                        get_either_table(target_toml, first, second, manifest_path)?;
+                    inherit_deps(
+                        deps,
+                        deps_toml,
+                        &format!("target.{target_key}.{table_name}"),
+                        &package_name2inherited_source,
+                        &mut changes,
+                    );
+                }
+            }
+        }
+        // The root manifest can be a package too: its edits must not be split
+        // across two plans, or writing the second one would discard the first.
+        if manifest_path == root.path {
+            root.changes.extend(changes);
+        } else if !changes.is_empty() {
+            members.push(ManifestPlan {
+                path: manifest_path.to_owned(),
+                changes,
+            });
         }
     }
 
+    let skipped = report
+        .dependencies
+        .iter()
+        .filter_map(|dependency| match &dependency.outcome {
+            Outcome::Skipped { reason } => Some(SkippedDependency {
+                name: dependency.name.clone(),
+                reason: reason.clone(),
+            }),
+            Outcome::Inherited { .. } => None,
+        })
+        .collect();
+    Ok(Plan {
+        prefer_simple_dotted: conf.prefer_simple_dotted,
+        root,
+        members,
+        skipped,
+        originals,
+    })
+}
+
+fn print_report(report: &Report, message_format: MessageFormat) -> Result<(), Error> {
+    match message_format {
+        MessageFormat::Human => report.print_human(),
+        MessageFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(report).map_err(Error::SerializeReport)?
+        ),
+    }
     Ok(())
 }
 
+fn read_manifest(path: &Path) -> Result<String, Error> {
+    fs_err::read_to_string(path).map_err(|e| Error::ReadManifest {
+        path: path.to_owned(),
+        source: e,
+    })
+}
+
+fn parse_manifest<T: serde::de::DeserializeOwned>(path: &Path, contents: &str) -> Result<T, Error> {
+    toml::from_str(contents).map_err(|e| Error::ParseManifest {
+        path: path.to_owned(),
+        source: Box::new(e),
+    })
+}
+
+fn parse_document(path: &Path, contents: &str) -> Result<toml_edit::DocumentMut, Error> {
+    contents.parse().map_err(|e| Error::ParseManifest {
+        path: path.to_owned(),
+        source: Box::new(e),
+    })
+}
+
+fn print_dry_run(edits: &[ManifestEdit]) {
+    if edits.is_empty() {
+        println!("No manifest would be modified.");
+        return;
+    }
+    for edit in edits {
+        println!("Would update `{}`:", edit.path.display());
+        for change in &edit.changes {
+            match &change.value {
+                Some(value) => println!("  {} = {value}", change.location()),
+                None => println!("  {} (removed)", change.location()),
+            }
+        }
+    }
+}
+
+/// Prints a unified diff between the manifest contents on disk and
+/// the contents that would be written.
+fn print_diff(edits: &[ManifestEdit], workspace_root: &Path) {
+    for edit in edits {
+        let path = edit
+            .path
+            .strip_prefix(workspace_root)
+            .unwrap_or(&edit.path)
+            .display();
+        let updated = edit.document.to_string();
+        let diff = similar::TextDiff::from_lines(&edit.original, &updated);
+        print!(
+            "{}",
+            diff.unified_diff()
+                .header(&format!("a/{path}"), &format!("b/{path}"))
+        );
+    }
+}
+
+fn check(edits: &[ManifestEdit]) -> Result<(), Error> {
+    if edits.is_empty() {
+        return Ok(());
+    }
+    for edit in edits {
+        eprintln!("`{}` would be modified:", edit.path.display());
+        for change in &edit.changes {
+            eprintln!("  {}", change.location());
+        }
+    }
+    Err(Error::CheckFailed {
+        manifests: edits.iter().map(|edit| edit.path.clone()).collect(),
+    })
+}
+
 enum Action {
     TryInherit(MinimalVersionSet),
     Skip,
@@ -320,12 +1033,13 @@ impl Default for Action {
     }
 }
 
+/// Records the changes needed for the entries of `deps` to inherit from the workspace.
 fn inherit_deps(
     deps: &DepsSet,
-    toml_deps: &mut toml_edit::Table,
+    toml_deps: &toml_edit::Table,
+    table_name: &str,
     package_name2spec: &BTreeMap<String, SharedDependency>,
-    was_modified: &mut bool,
-    prefer_simple_dotted: bool,
+    changes: &mut Vec<EntryChange>,
 ) {
     for (name, dep) in deps {
         let package_name = dep.package().unwrap_or(name.as_str());
@@ -336,10 +1050,9 @@ fn inherit_deps(
             Dependency::Simple(_) => {
                 let mut inherited = toml_edit::InlineTable::new();
                 inherited.insert("workspace", toml_edit::value(true).into_value().unwrap());
-                inherited.set_dotted(prefer_simple_dotted);
 
-                insert_preserving_decor(toml_deps, name, toml_edit::Item::Value(inherited.into()));
-                *was_modified = true;
+                let item = toml_edit::Item::Value(inherited.into());
+                changes.push(EntryChange::new(table_name, name, &item));
             }
             Dependency::Inherited(_) => {
                 // Nothing to do.
@@ -356,16 +1069,156 @@ fn inherit_deps(
                 if let Some(optional) = details.optional {
                     inherited.insert("optional", toml_edit::value(optional).into_value().unwrap());
                 }
-
-                if inherited.len() == 1 {
-                    inherited.set_dotted(prefer_simple_dotted);
+                // Carry over the keys we don't model, but that Cargo accepts on inherited entries.
+                if let Some(existing) = toml_deps.get(name).and_then(|d| d.as_table_like()) {
+                    for key in INHERITABLE_UNKNOWN_DEPENDENCY_KEYS {
+                        if let Some(value) = existing.get(key).and_then(|v| v.as_value()) {
+                            let mut value = value.clone();
+                            value.decor_mut().clear();
+                            inherited.insert(*key, value);
+                        }
+                    }
                 }
 
-                insert_preserving_decor(toml_deps, name, toml_edit::Item::Value(inherited.into()));
-                *was_modified = true;
+                let item = toml_edit::Item::Value(inherited.into());
+                changes.push(EntryChange::new(table_name, name, &item));
+            }
+        }
+    }
+}
+
+/// The keys of a dependency entry that are modelled by [`DependencyDetail`].
+const KNOWN_DEPENDENCY_KEYS: &[&str] = &[
+    "version",
+    "registry",
+    "registry-index",
+    "path",
+    "git",
+    "branch",
+    "tag",
+    "rev",
+    "features",
+    "optional",
+    "default-features",
+    "default_features",
+    "package",
+];
+
+/// The keys of a dependency entry that aren't modelled by [`DependencyDetail`],
+/// but that Cargo accepts next to `workspace = true`.
+/// They're kept as-is on the member's inherited entry.
+const INHERITABLE_UNKNOWN_DEPENDENCY_KEYS: &[&str] = &["public"];
+
+/// Drops the dependencies that a member asks to leave untouched,
+/// so that they're neither taken into account nor rewritten.
+fn remove_skipped_deps(manifest: &mut Manifest, config: &MemberConfig) {
+    let retain = |deps: &mut DepsSet, kind| {
+        deps.retain(|name, dep| !config.skips(kind, name, dep.package().unwrap_or(name)));
+    };
+    for (deps, kind) in [
+        (&mut manifest.dependencies, DependencyKind::Normal),
+        (&mut manifest.dev_dependencies, DependencyKind::Dev),
+        (&mut manifest.build_dependencies, DependencyKind::Build),
+    ] {
+        if let Some(deps) = deps {
+            retain(deps, kind);
+        }
+    }
+    for target in manifest.target.iter_mut().flat_map(|t| t.values_mut()) {
+        retain(&mut target.dependencies, DependencyKind::Normal);
+        retain(&mut target.dev_dependencies, DependencyKind::Dev);
+        retain(&mut target.build_dependencies, DependencyKind::Build);
+    }
+}
+
+/// Like [`remove_skipped_deps`], for a manifest that hasn't been deserialized into a [`Manifest`].
+fn remove_skipped_raw_deps(manifest: &mut toml::Table, config: &MemberConfig) {
+    fn retain(table: &mut toml::Table, config: &MemberConfig) {
+        for (key, kind) in [
+            ("dependencies", DependencyKind::Normal),
+            ("dev-dependencies", DependencyKind::Dev),
+            ("dev_dependencies", DependencyKind::Dev),
+            ("build-dependencies", DependencyKind::Build),
+            ("build_dependencies", DependencyKind::Build),
+        ] {
+            let Some(deps) = table.get_mut(key).and_then(|d| d.as_table_mut()) else {
+                continue;
+            };
+            let skipped: Vec<String> = deps
+                .iter()
+                .filter(|(name, dep)| {
+                    let package_name = dep.get("package").and_then(|p| p.as_str());
+                    config.skips(kind, name, package_name.unwrap_or(name))
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in skipped {
+                deps.remove(&name);
             }
         }
     }
+
+    retain(manifest, config);
+    if let Some(targets) = manifest.get_mut("target").and_then(|t| t.as_table_mut()) {
+        for (_, target) in targets.iter_mut() {
+            let Some(target) = target.as_table_mut() else {
+                continue;
+            };
+            retain(target, config);
+        }
+    }
+}
+
+/// All the dependency tables in a member manifest, including target-specific ones.
+fn member_dependency_tables(manifest: &toml::Table) -> Vec<&toml::Table> {
+    let targets = manifest
+        .get("target")
+        .and_then(|t| t.as_table())
+        .into_iter()
+        .flat_map(|targets| targets.values().filter_map(|t| t.as_table()));
+    std::iter::once(manifest)
+        .chain(targets)
+        .flat_map(|table| {
+            [
+                "dependencies",
+                "dev-dependencies",
+                "dev_dependencies",
+                "build-dependencies",
+                "build_dependencies",
+            ]
+            .into_iter()
+            .filter_map(|key| table.get(key).and_then(|d| d.as_table()))
+        })
+        .collect()
+}
+
+/// Collects the keys of each dependency entry that we'd lose if the dependency
+/// was inherited—e.g. `artifact`, `lib` or `target` for artifact dependencies.
+fn collect_unsupported_keys(
+    deps: &toml::Table,
+    package_name2unsupported_keys: &mut BTreeMap<String, BTreeSet<String>>,
+) {
+    for (name, dep) in deps {
+        let Some(dep) = dep.as_table() else {
+            continue;
+        };
+        // Entries that are already inherited are left untouched.
+        if dep.contains_key("workspace") {
+            continue;
+        }
+        let package_name = dep.get("package").and_then(|p| p.as_str()).unwrap_or(name);
+        for key in dep.keys() {
+            if KNOWN_DEPENDENCY_KEYS.contains(&key.as_str())
+                || INHERITABLE_UNKNOWN_DEPENDENCY_KEYS.contains(&key.as_str())
+            {
+                continue;
+            }
+            package_name2unsupported_keys
+                .entry(package_name.to_owned())
+                .or_default()
+                .insert(key.clone());
+        }
+    }
 }
 
 fn insert_preserving_decor(table: &mut toml_edit::Table, key: &str, mut value: toml_edit::Item) {
@@ -404,32 +1257,59 @@ fn insert_preserving_decor(table: &mut toml_edit::Table, key: &str, mut value: t
     table.insert_formatted(&new_key, value);
 }
 
-fn process_deps(deps: &DepsSet, package_name2specs: &mut BTreeMap<String, Action>) {
+/// Collects the sources of `deps`, keyed by package name rather than by dependency key,
+/// so that renamed dependencies are merged with the package they refer to.
+fn process_deps(
+    deps: &DepsSet,
+    manifest_path: &Path,
+    package_name2specs: &mut BTreeMap<String, Action>,
+    package_name2aliases: &mut BTreeMap<String, BTreeSet<String>>,
+) -> Result<(), Error> {
     for (name, details) in deps {
-        match dep2shared_dep(details) {
+        let package_name = details.package().unwrap_or(name);
+        let source = dep2shared_dep(details).map_err(|e| Error::InvalidRequirement {
+            path: manifest_path.to_owned(),
+            dependency: name.clone(),
+            source: e,
+        })?;
+        match source {
             SourceType::Shareable(source) => {
-                let action = package_name2specs.entry(name.clone()).or_default();
+                let action = package_name2specs
+                    .entry(package_name.to_owned())
+                    .or_default();
                 if let Action::TryInherit(set) = action {
                     set.insert(source);
                 }
+                package_name2aliases
+                    .entry(package_name.to_owned())
+                    .or_default()
+                    .insert(name.clone());
             }
             SourceType::Inherited => {}
             SourceType::MustBeSkipped => {
-                package_name2specs.insert(name.clone(), Action::Skip);
+                package_name2specs.insert(package_name.to_owned(), Action::Skip);
             }
         }
     }
+    Ok(())
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
-struct SharedDependency {
-    default_features: bool,
-    source: DependencySource,
+/// A dependency that can be declared once in `[workspace.dependencies]`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct SharedDependency {
+    pub default_features: bool,
+    pub source: DependencySource,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
-enum DependencySource {
+/// Where a shared dependency comes from.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencySource {
     Version(VersionReq),
+    Registry {
+        registry: String,
+        version: VersionReq,
+    },
     Git {
         git: String,
         branch: Option<String>,
@@ -447,6 +1327,9 @@ impl std::fmt::Display for DependencySource {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             DependencySource::Version(version) => write!(f, "version: {}", version),
+            DependencySource::Registry { registry, version } => {
+                write!(f, "registry: {}, version: {}", registry, version)
+            }
             DependencySource::Git {
                 git,
                 branch,
@@ -486,11 +1369,10 @@ enum SourceType {
     MustBeSkipped,
 }
 
-fn dep2shared_dep(dep: &Dependency) -> SourceType {
-    match dep {
+fn dep2shared_dep(dep: &Dependency) -> Result<SourceType, semver::Error> {
+    let source_type = match dep {
         Dependency::Simple(version) => {
-            let version_req =
-                VersionReq::parse(version).expect("Failed to parse version requirement");
+            let version_req = VersionReq::parse(version)?;
             SourceType::Shareable(SharedDependency {
                 default_features: true,
                 source: DependencySource::Version(version_req),
@@ -499,16 +1381,27 @@ fn dep2shared_dep(dep: &Dependency) -> SourceType {
         Dependency::Inherited(_) => SourceType::Inherited,
         Dependency::Detailed(d) => {
             let mut source = None;
-            // We ignore custom registries for now.
-            if d.registry.is_some() || d.registry_index.is_some() {
-                return SourceType::MustBeSkipped;
+            // Registries are only supported by name, not by index URL.
+            if d.registry_index.is_some() {
+                return Ok(SourceType::MustBeSkipped);
             }
-            if d.path.is_some() {
+            if let Some(registry) = &d.registry {
+                // A registry dependency that's also sourced from a path or a git repository
+                // is only published to that registry, we can't share it as-is.
+                if d.path.is_some() || d.git.is_some() {
+                    return Ok(SourceType::MustBeSkipped);
+                }
+                if let Some(version) = &d.version {
+                    let version_req = VersionReq::parse(version)?;
+                    source = Some(DependencySource::Registry {
+                        registry: registry.to_owned(),
+                        version: version_req,
+                    });
+                }
+            } else if let Some(path) = &d.path {
                 source = Some(DependencySource::Path {
-                    path: d.path.as_ref().unwrap().to_owned(),
-                    version: d.version.as_ref().map(|v| {
-                        VersionReq::parse(v).expect("Failed to parse version requirement")
-                    }),
+                    path: path.to_owned(),
+                    version: d.version.as_deref().map(VersionReq::parse).transpose()?,
                 });
             } else if let Some(git) = &d.git {
                 source = Some(DependencySource::Git {
@@ -516,13 +1409,10 @@ fn dep2shared_dep(dep: &Dependency) -> SourceType {
                     branch: d.branch.to_owned(),
                     tag: d.tag.to_owned(),
                     rev: d.rev.to_owned(),
-                    version: d.version.as_ref().map(|v| {
-                        VersionReq::parse(v).expect("Failed to parse version requirement")
-                    }),
+                    version: d.version.as_deref().map(VersionReq::parse).transpose()?,
                 });
             } else if let Some(version) = &d.version {
-                let version_req =
-                    VersionReq::parse(version).expect("Failed to parse version requirement");
+                let version_req = VersionReq::parse(version)?;
                 source = Some(DependencySource::Version(version_req));
             }
             match source {
@@ -533,26 +1423,41 @@ fn dep2shared_dep(dep: &Dependency) -> SourceType {
                 }),
             }
         }
-    }
+    };
+    Ok(source_type)
 }
 
-fn shared2dep(shared_dependency: &SharedDependency) -> Dependency {
+/// Converts a shared dependency back into a manifest entry.
+///
+/// `package` must be set when the entry is keyed by an alias rather than by the package name.
+fn shared2dep(shared_dependency: &SharedDependency, package: Option<&str>) -> Dependency {
     let SharedDependency {
         default_features,
         source,
     } = shared_dependency;
+    let package = package.map(ToOwned::to_owned);
     match source {
         DependencySource::Version(version) => {
-            if *default_features {
+            if *default_features && package.is_none() {
                 Dependency::Simple(version.to_string())
             } else {
                 Dependency::Detailed(DependencyDetail {
+                    package,
                     version: Some(version.to_string()),
-                    default_features: Some(false),
+                    default_features: if *default_features { None } else { Some(false) },
                     ..DependencyDetail::default()
                 })
             }
         }
+        DependencySource::Registry { registry, version } => {
+            Dependency::Detailed(DependencyDetail {
+                package,
+                version: Some(version.to_string()),
+                registry: Some(registry.clone()),
+                default_features: if *default_features { None } else { Some(false) },
+                ..DependencyDetail::default()
+            })
+        }
         DependencySource::Git {
             git,
             branch,
@@ -560,7 +1465,7 @@ fn shared2dep(shared_dependency: &SharedDependency) -> Dependency {
             rev,
             version,
         } => Dependency::Detailed(DependencyDetail {
-            package: None,
+            package,
             version: version.as_ref().map(|v| v.to_string()),
             registry: None,
             registry_index: None,
@@ -574,7 +1479,7 @@ fn shared2dep(shared_dependency: &SharedDependency) -> Dependency {
             default_features: if *default_features { None } else { Some(false) },
         }),
         DependencySource::Path { path, version } => Dependency::Detailed(DependencyDetail {
-            package: None,
+            package,
             version: version.as_ref().map(|v| v.to_string()),
             registry: None,
             registry_index: None,
@@ -590,6 +1495,78 @@ fn shared2dep(shared_dependency: &SharedDependency) -> Dependency {
     }
 }
 
+/// Collects the changes to make to each member when hoisting something other than dependencies.
+///
+/// [`package_fields`], [`lints`] and [`root_sections`] push the entries to add to the root
+/// manifest to the changes they're given, and return the ones to make in each member,
+/// keyed by manifest path.
+fn merge_member_changes(
+    member2changes: &mut BTreeMap<PathBuf, Vec<EntryChange>>,
+    changes: BTreeMap<PathBuf, Vec<EntryChange>>,
+) {
+    for (manifest_path, changes) in changes {
+        member2changes
+            .entry(manifest_path)
+            .or_default()
+            .extend(changes);
+    }
+}
+
+/// Rewrites the version requirement of an existing `[workspace.dependencies]` entry to the one
+/// of `source`, keeping the rest of its keys (e.g. `features`).
+///
+/// Returns `None` if the entry doesn't point to the same registry as `source`.
+fn update_workspace_dep(
+    alias: &str,
+    existing: &toml_edit::Item,
+    source: &DependencySource,
+) -> Option<EntryChange> {
+    let entry = existing.as_table_like()?;
+    let registry = entry.get("registry").and_then(|r| r.as_str());
+    let version = match source {
+        DependencySource::Version(version) if registry.is_none() => version,
+        DependencySource::Registry {
+            registry: source_registry,
+            version,
+        } if registry == Some(source_registry.as_str()) => version,
+        _ => return None,
+    };
+    if ["git", "branch", "tag", "rev", "path", "registry-index"]
+        .iter()
+        .any(|key| entry.contains_key(key))
+    {
+        return None;
+    }
+
+    let version = toml_edit::Value::from(version.to_string().trim_start_matches('^'));
+    if existing.is_table() {
+        let table = format!("workspace.dependencies.{}", Key::new(alias));
+        return Some(EntryChange::new(
+            &table,
+            "version",
+            &toml_edit::Item::Value(version),
+        ));
+    }
+    let mut entry = existing.as_inline_table()?.clone();
+    // The decor of the existing entry is carried over when the plan is applied.
+    entry.decor_mut().clear();
+    match entry.get_mut("version") {
+        Some(existing_version) => {
+            let decor = existing_version.decor().clone();
+            *existing_version = version;
+            *existing_version.decor_mut() = decor;
+        }
+        None => {
+            entry.insert("version", version);
+        }
+    }
+    Some(EntryChange::new(
+        "workspace.dependencies",
+        alias,
+        &toml_edit::Item::Value(entry.into()),
+    ))
+}
+
 fn dep2toml_item(dependency: &Dependency) -> toml_edit::Item {
     match dependency {
         Dependency::Simple(version) => toml_edit::value(version.trim_start_matches('^').to_owned()),
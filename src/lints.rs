@@ -0,0 +1,231 @@
+use crate::most_common;
+use crate::plan::EntryChange;
+use crate::report::{LintsOutcome, LintsSkipReason, Report};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use toml_edit::Key;
+
+/// The `[lints]` table of a workspace member.
+pub(crate) struct MemberLints {
+    pub(crate) name: String,
+    pub(crate) manifest_path: PathBuf,
+    pub(crate) lints: toml::Table,
+}
+
+/// Works out which members can replace their `[lints]` with `workspace = true`.
+///
+/// If the root manifest has no `[workspace.lints]`, the lints shared by the most members
+/// are hoisted into it, as long as at least two members share them and no other set of lints
+/// is as common.
+/// Lints are compared once normalized: `unsafe_code = "forbid"` is the same as
+/// `unsafe_code = { level = "forbid", priority = 0 }`.
+/// Members whose lints differ keep their own, and are reported.
+pub(crate) fn inherit_lints(
+    workspace_lints: Option<&toml::Table>,
+    members: &[MemberLints],
+    report: &mut Report,
+    root_changes: &mut Vec<EntryChange>,
+) -> BTreeMap<PathBuf, Vec<EntryChange>> {
+    let members: Vec<_> = members
+        .iter()
+        .filter(|member| !member.lints.contains_key("workspace"))
+        .map(|member| (member, normalize(&member.lints)))
+        .collect();
+
+    let inherited = match workspace_lints {
+        Some(lints) => normalize(lints),
+        None => {
+            let Some(most_common) = most_common(members.iter().map(|(_, lints)| lints)) else {
+                if members.len() > 1 {
+                    for (member, _) in &members {
+                        report.member_lints(
+                            &member.name,
+                            LintsOutcome::Skipped {
+                                reason: LintsSkipReason::NoSharedLints,
+                            },
+                        );
+                    }
+                }
+                return BTreeMap::new();
+            };
+            let (member, _) = members
+                .iter()
+                .find(|(_, lints)| lints == most_common)
+                .unwrap();
+            for (tool, tool_lints) in &member.lints {
+                let Some(tool_lints) = tool_lints.as_table() else {
+                    root_changes.push(lint_entry("workspace.lints", tool, tool_lints));
+                    continue;
+                };
+                let table = format!("workspace.lints.{}", Key::new(tool));
+                for (lint, config) in tool_lints {
+                    root_changes.push(lint_entry(&table, lint, config));
+                }
+            }
+            most_common.clone()
+        }
+    };
+
+    let mut member_changes = BTreeMap::new();
+    for (member, lints) in &members {
+        if *lints != inherited {
+            report.member_lints(
+                &member.name,
+                LintsOutcome::Skipped {
+                    reason: LintsSkipReason::DifferentLints,
+                },
+            );
+            continue;
+        }
+        let mut changes: Vec<_> = member
+            .lints
+            .keys()
+            .map(|tool| EntryChange::remove("lints", tool))
+            .collect();
+        changes.push(EntryChange::new(
+            "lints",
+            "workspace",
+            &toml_edit::value(true),
+        ));
+        member_changes.insert(member.manifest_path.clone(), changes);
+        report.member_lints(&member.name, LintsOutcome::Inherited);
+    }
+    member_changes
+}
+
+fn lint_entry(table: &str, key: &str, value: &toml::Value) -> EntryChange {
+    EntryChange {
+        table: table.to_owned(),
+        key: key.to_owned(),
+        value: Some(value.to_string()),
+    }
+}
+
+fn normalize(lints: &toml::Table) -> toml::Table {
+    lints
+        .iter()
+        .map(|(tool, tool_lints)| {
+            let tool_lints = match tool_lints.as_table() {
+                Some(tool_lints) => toml::Value::Table(
+                    tool_lints
+                        .iter()
+                        .map(|(lint, config)| (lint.clone(), normalize_lint(config)))
+                        .collect(),
+                ),
+                None => tool_lints.clone(),
+            };
+            (tool.clone(), tool_lints)
+        })
+        .collect()
+}
+
+/// `lint = "warn"` is short for `lint = { level = "warn", priority = 0 }`.
+fn normalize_lint(config: &toml::Value) -> toml::Value {
+    let mut config = match config {
+        toml::Value::String(_) => toml::Table::from_iter([("level".to_owned(), config.clone())]),
+        toml::Value::Table(config) => config.clone(),
+        _ => return config.clone(),
+    };
+    if config.get("priority").and_then(|p| p.as_integer()) == Some(0) {
+        config.remove("priority");
+    }
+    toml::Value::Table(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::MemberLintsReport;
+    use std::path::Path;
+
+    fn member(name: &str, lints: &str) -> MemberLints {
+        MemberLints {
+            name: name.to_owned(),
+            manifest_path: PathBuf::from(format!("/ws/{name}/Cargo.toml")),
+            lints: toml::from_str(lints).unwrap(),
+        }
+    }
+
+    fn table(lints: &str) -> toml::Table {
+        toml::from_str(lints).unwrap()
+    }
+
+    #[test]
+    fn shorthand_and_table_forms_are_equal() {
+        assert_eq!(
+            normalize(&table("rust.unsafe_code = \"forbid\"")),
+            normalize(&table(
+                "rust.unsafe_code = { level = \"forbid\", priority = 0 }"
+            ))
+        );
+        assert_ne!(
+            normalize(&table("rust.unsafe_code = \"forbid\"")),
+            normalize(&table(
+                "rust.unsafe_code = { level = \"forbid\", priority = 1 }"
+            ))
+        );
+    }
+
+    #[test]
+    fn tied_lints_are_not_hoisted() {
+        let members = [
+            member("a", "rust.unsafe_code = \"forbid\""),
+            member("b", "rust.unsafe_code = \"deny\""),
+        ];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = inherit_lints(None, &members, &mut report, &mut root_changes);
+
+        assert!(root_changes.is_empty());
+        assert!(member_changes.is_empty());
+        assert!(report.lints.iter().all(|member| matches!(
+            member.outcome,
+            LintsOutcome::Skipped {
+                reason: LintsSkipReason::NoSharedLints
+            }
+        )));
+        assert_eq!(report.lints.len(), 2);
+    }
+
+    #[test]
+    fn members_that_differ_from_workspace_lints_keep_theirs() {
+        let workspace_lints = table("rust.unsafe_code = { level = \"forbid\" }");
+        let members = [
+            member("a", "rust.unsafe_code = \"forbid\""),
+            member("b", "rust.unsafe_code = \"deny\""),
+        ];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = inherit_lints(
+            Some(&workspace_lints),
+            &members,
+            &mut report,
+            &mut root_changes,
+        );
+
+        assert!(root_changes.is_empty());
+        assert_eq!(
+            member_changes[Path::new("/ws/a/Cargo.toml")],
+            [
+                EntryChange::remove("lints", "rust"),
+                EntryChange::new("lints", "workspace", &toml_edit::value(true)),
+            ]
+        );
+        assert!(!member_changes.contains_key(Path::new("/ws/b/Cargo.toml")));
+        assert!(matches!(
+            &report.lints[..],
+            [
+                MemberLintsReport {
+                    outcome: LintsOutcome::Inherited,
+                    ..
+                },
+                MemberLintsReport {
+                    outcome: LintsOutcome::Skipped {
+                        reason: LintsSkipReason::DifferentLints
+                    },
+                    ..
+                },
+            ]
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+use crate::report::DependencyKind;
+use crate::{parse_manifest, read_manifest, Error};
+use guppy::CargoMetadata;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The root of a workspace and its members.
+pub(crate) struct WorkspaceMembers {
+    pub(crate) root: PathBuf,
+    /// Sorted by manifest path.
+    pub(crate) members: Vec<Member>,
+}
+
+pub(crate) struct Member {
+    pub(crate) name: String,
+    pub(crate) manifest_path: PathBuf,
+    /// The versions resolved in `Cargo.lock` for the direct dependencies of the member,
+    /// as `(package name, version)` pairs.
+    /// Empty if the dependency graph wasn't resolved.
+    pub(crate) locked_versions: Vec<(String, guppy::Version)>,
+    pub(crate) config: MemberConfig,
+}
+
+/// The settings of a member, from its own `[package.metadata.cargo-autoinherit]` table.
+#[derive(Default)]
+pub(crate) struct MemberConfig {
+    /// Leave the member untouched, as if it was excluded in the workspace metadata.
+    pub(crate) skip: bool,
+    /// The dependencies to leave untouched, matching their package name or the key
+    /// they're declared under.
+    skip_dependencies: Vec<glob::Pattern>,
+    /// The kinds of dependencies to leave untouched.
+    skip_kinds: Vec<DependencyKind>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct RawMemberConfig {
+    skip: bool,
+    skip_dependencies: Vec<String>,
+    skip_kinds: Vec<DependencyKind>,
+}
+
+impl MemberConfig {
+    /// Parses the `cargo-autoinherit` entry of the package metadata, if any.
+    fn parse(config: Option<serde_json::Value>, manifest_path: &Path) -> Result<Self, Error> {
+        let invalid =
+            |source: Box<dyn std::error::Error + Send + Sync>| Error::InvalidMemberMetadata {
+                path: manifest_path.to_owned(),
+                source,
+            };
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let config: RawMemberConfig =
+            serde_json::from_value(config).map_err(|e| invalid(Box::new(e)))?;
+        let skip_dependencies = config
+            .skip_dependencies
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).map_err(|e| invalid(Box::new(e))))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            skip: config.skip,
+            skip_dependencies,
+            skip_kinds: config.skip_kinds,
+        })
+    }
+
+    /// Whether the dependency declared under `name` must be left untouched.
+    pub(crate) fn skips(&self, kind: DependencyKind, name: &str, package_name: &str) -> bool {
+        self.skip_kinds.contains(&kind)
+            || self
+                .skip_dependencies
+                .iter()
+                .any(|pattern| pattern.matches(name) || pattern.matches(package_name))
+    }
+}
+
+impl WorkspaceMembers {
+    /// Runs `cargo metadata`, resolving the whole dependency graph.
+    pub(crate) fn from_cargo_metadata(manifest_path: Option<&Path>) -> Result<Self, Error> {
+        let mut metadata_command = guppy::MetadataCommand::new();
+        if let Some(manifest_path) = manifest_path {
+            metadata_command.manifest_path(manifest_path);
+        }
+        let metadata = metadata_command.exec().map_err(Error::CargoMetadata)?;
+        Self::from_metadata(metadata)
+    }
+
+    /// Reads the output of `cargo metadata` from a file, instead of running it.
+    pub(crate) fn from_metadata_file(path: &Path) -> Result<Self, Error> {
+        let json = fs_err::read_to_string(path).map_err(|e| Error::ReadMetadata {
+            path: path.to_owned(),
+            source: e,
+        })?;
+        let metadata = CargoMetadata::parse_json(json).map_err(|e| Error::ParseMetadata {
+            path: path.to_owned(),
+            source: e,
+        })?;
+        Self::from_metadata(metadata)
+    }
+
+    fn from_metadata(metadata: CargoMetadata) -> Result<Self, Error> {
+        let graph = metadata.build_graph().map_err(Error::PackageGraph)?;
+
+        let mut members = Vec::new();
+        for member_id in graph.workspace().member_ids() {
+            let package = graph.metadata(member_id).map_err(Error::PackageGraph)?;
+            let manifest_path = package.manifest_path().as_std_path();
+            let config = package.metadata_table().get("cargo-autoinherit").cloned();
+            members.push(Member {
+                name: package.name().to_owned(),
+                manifest_path: manifest_path.to_owned(),
+                locked_versions: package
+                    .direct_links()
+                    .map(|link| {
+                        let dependency = link.to();
+                        (dependency.name().to_owned(), dependency.version().clone())
+                    })
+                    .collect(),
+                config: MemberConfig::parse(config, manifest_path)?,
+            });
+        }
+        members.sort_by(|a, b| a.manifest_path.cmp(&b.manifest_path));
+        Ok(Self {
+            root: graph.workspace().root().as_std_path().to_owned(),
+            members,
+        })
+    }
+
+    /// Finds the members listed in `workspace.members`, minus those in `workspace.exclude`,
+    /// without invoking Cargo.
+    ///
+    /// Like Cargo, the search for the root manifest starts from `manifest_path`,
+    /// or the closest `Cargo.toml` in the current directory or above it.
+    /// The root manifest is the first one on the way up with a `[workspace]` table.
+    /// Path dependencies that Cargo implicitly adds to the workspace aren't included.
+    pub(crate) fn from_root_manifest(manifest_path: Option<&Path>) -> Result<Self, Error> {
+        let current_dir = std::env::current_dir().map_err(Error::CurrentDir)?;
+        let start = match manifest_path {
+            Some(manifest_path) => current_dir.join(manifest_path),
+            None => current_dir
+                .ancestors()
+                .map(|dir| dir.join("Cargo.toml"))
+                .find(|candidate| candidate.is_file())
+                .ok_or_else(|| Error::ManifestNotFound {
+                    path: current_dir.clone(),
+                })?,
+        };
+        let (root_manifest_path, root_manifest) = find_root_manifest(&start)?;
+        let root = root_manifest_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_owned();
+        let workspace = root_manifest
+            .get("workspace")
+            .and_then(|w| w.as_table())
+            .ok_or_else(|| Error::NotAWorkspace {
+                path: root_manifest_path.clone(),
+            })?;
+        let members = string_array(workspace, "members", &root_manifest_path)?;
+        let excluded = string_array(workspace, "exclude", &root_manifest_path)?
+            .into_iter()
+            .map(|path| root.join(path))
+            .collect::<Vec<_>>();
+
+        let mut member_dirs = Vec::new();
+        if root_manifest.contains_key("package") {
+            member_dirs.push(root.clone());
+        }
+        let escaped_root = glob::Pattern::escape(
+            root.to_str()
+                .ok_or_else(|| Error::NonUtf8Path { path: root.clone() })?,
+        );
+        for pattern in members {
+            let is_glob = pattern.contains(['*', '?', '[']);
+            // Unlike globs, paths must point to a package.
+            if !is_glob && !root.join(&pattern).join("Cargo.toml").is_file() {
+                return Err(Error::MissingMember {
+                    path: root.join(&pattern).join("Cargo.toml"),
+                    member: pattern,
+                });
+            }
+            let paths = glob::glob(&format!("{escaped_root}/{pattern}")).map_err(|e| {
+                Error::InvalidMemberPattern {
+                    pattern: pattern.clone(),
+                    source: e,
+                }
+            })?;
+            for path in paths {
+                let path = path.map_err(|e| Error::DiscoverMembers {
+                    path: e.path().to_owned(),
+                    source: e.into(),
+                })?;
+                // Globs may match directories that aren't packages, e.g. `crates/README.md`.
+                if is_glob && !path.join("Cargo.toml").is_file() {
+                    continue;
+                }
+                member_dirs.push(path);
+            }
+        }
+
+        let mut members = BTreeMap::new();
+        for dir in member_dirs {
+            if excluded.iter().any(|excluded| dir.starts_with(excluded)) {
+                continue;
+            }
+            let manifest_path = dir.join("Cargo.toml");
+            if members.contains_key(&manifest_path) {
+                continue;
+            }
+            let manifest: toml::Table =
+                parse_manifest(&manifest_path, &read_manifest(&manifest_path)?)?;
+            let name = manifest
+                .get("package")
+                .and_then(|p| p.get("name"))
+                .and_then(|n| n.as_str())
+                .ok_or_else(|| Error::UnexpectedToml {
+                    path: manifest_path.clone(),
+                    key: "package.name".to_owned(),
+                    expected: "a string",
+                })?;
+            let config = manifest
+                .get("package")
+                .and_then(|p| p.get("metadata"))
+                .and_then(|m| m.get("cargo-autoinherit"))
+                .map(|config| {
+                    serde_json::to_value(config).map_err(|e| Error::InvalidMemberMetadata {
+                        path: manifest_path.clone(),
+                        source: Box::new(e),
+                    })
+                })
+                .transpose()?;
+            let config = MemberConfig::parse(config, &manifest_path)?;
+            members.insert(
+                manifest_path.clone(),
+                Member {
+                    name: name.to_owned(),
+                    manifest_path,
+                    locked_versions: Vec::new(),
+                    config,
+                },
+            );
+        }
+        Ok(Self {
+            root,
+            members: members.into_values().collect(),
+        })
+    }
+}
+
+/// Returns `start` if it's a workspace manifest, or the closest `Cargo.toml` above it that is.
+fn find_root_manifest(start: &Path) -> Result<(PathBuf, toml::Table), Error> {
+    let candidates = std::iter::once(start.to_owned()).chain(
+        start
+            .parent()
+            .into_iter()
+            .flat_map(Path::ancestors)
+            .skip(1)
+            .map(|dir| dir.join("Cargo.toml")),
+    );
+    for candidate in candidates {
+        if candidate != start && !candidate.is_file() {
+            continue;
+        }
+        let manifest: toml::Table = parse_manifest(&candidate, &read_manifest(&candidate)?)?;
+        if manifest.contains_key("workspace") {
+            return Ok((candidate, manifest));
+        }
+    }
+    Err(Error::NotAWorkspace {
+        path: start.to_owned(),
+    })
+}
+
+fn string_array(
+    workspace: &toml::Table,
+    key: &str,
+    manifest_path: &Path,
+) -> Result<Vec<String>, Error> {
+    let Some(value) = workspace.get(key) else {
+        return Ok(Vec::new());
+    };
+    value
+        .as_array()
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|v| v.as_str().map(ToOwned::to_owned))
+                .collect()
+        })
+        .ok_or_else(|| Error::UnexpectedToml {
+            path: manifest_path.to_owned(),
+            key: format!("workspace.{key}"),
+            expected: "an array of strings",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a fresh directory with the given files.
+    fn workspace(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cargo-autoinherit-{name}-{}", std::process::id()));
+        let _ = fs_err::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn package(name: &str) -> String {
+        format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n")
+    }
+
+    #[test]
+    fn globs_match_packages_only() {
+        let dir = workspace(
+            "globs",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\n\
+                    members = [\"crates/*\", \"tools/cli\"]\n\
+                    exclude = [\"crates/excluded\"]\n",
+                ),
+                ("crates/a/Cargo.toml", &package("a")),
+                ("crates/b/Cargo.toml", &package("b")),
+                ("crates/excluded/Cargo.toml", &package("excluded")),
+                ("crates/docs/index.md", ""),
+                ("crates/README.md", ""),
+                ("tools/cli/Cargo.toml", &package("cli")),
+            ],
+        );
+
+        let workspace =
+            WorkspaceMembers::from_root_manifest(Some(&dir.join("Cargo.toml"))).unwrap();
+        assert_eq!(workspace.root, dir);
+        let members: Vec<_> = workspace
+            .members
+            .iter()
+            .map(|member| (member.name.as_str(), member.manifest_path.clone()))
+            .collect();
+        assert_eq!(
+            members,
+            [
+                ("a", dir.join("crates/a/Cargo.toml")),
+                ("b", dir.join("crates/b/Cargo.toml")),
+                ("cli", dir.join("tools/cli/Cargo.toml")),
+            ]
+        );
+        fs_err::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_members_are_an_error() {
+        let dir = workspace(
+            "missing",
+            &[
+                ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+                ("a/Cargo.toml", &package("a")),
+            ],
+        );
+
+        let result = WorkspaceMembers::from_root_manifest(Some(&dir.join("Cargo.toml")));
+        assert!(matches!(
+            result,
+            Err(Error::MissingMember { ref member, .. }) if member == "b"
+        ));
+        fs_err::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use crate::plan::EntryChange;
+use crate::report::{FieldOutcome, FieldSkipReason, FieldUsage, Report};
+use crate::{most_common, rebase_path, Error};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The `[package]` fields that a member can inherit from `[workspace.package]`.
+const INHERITABLE_FIELDS: &[&str] = &[
+    "version",
+    "edition",
+    "rust-version",
+    "authors",
+    "license",
+    "repository",
+    "homepage",
+    "documentation",
+    "readme",
+    "keywords",
+    "categories",
+    "publish",
+    "description",
+];
+
+/// The `[package]` table of a workspace member.
+pub(crate) struct MemberPackage {
+    pub(crate) name: String,
+    pub(crate) manifest_path: PathBuf,
+    pub(crate) package: toml::Table,
+}
+
+/// Works out which `[package]` fields can be inherited from `[workspace.package]`.
+///
+/// A field that isn't set in the root manifest is hoisted with the value shared by the most
+/// members, as long as at least two members share it and no other value is as common.
+/// Members inherit a field only if they set it to the value found in `[workspace.package]`;
+/// the others keep their own, and are reported.
+pub(crate) fn inherit_package_fields(
+    workspace_root: &Path,
+    workspace_package: Option<&toml::Table>,
+    members: &[MemberPackage],
+    report: &mut Report,
+    root_changes: &mut Vec<EntryChange>,
+) -> Result<BTreeMap<PathBuf, Vec<EntryChange>>, Error> {
+    let mut member_changes: BTreeMap<PathBuf, Vec<EntryChange>> = BTreeMap::new();
+    for field in INHERITABLE_FIELDS {
+        let mut usages = Vec::new();
+        for member in members {
+            let Some(value) = member.package.get(*field) else {
+                continue;
+            };
+            if value
+                .as_table()
+                .is_some_and(|t| t.contains_key("workspace"))
+            {
+                continue;
+            }
+            let manifest_dir = member.manifest_path.parent().unwrap_or(Path::new(""));
+            usages.push((member, rebase(field, value, manifest_dir, workspace_root)?));
+        }
+
+        let inherited = match workspace_package.and_then(|p| p.get(*field)) {
+            Some(value) => rebase(field, value, workspace_root, workspace_root)?,
+            None => {
+                let Some(most_common) = most_common(usages.iter().map(|(_, value)| value)) else {
+                    if usages.len() > 1 {
+                        report.package_field(
+                            field,
+                            field_usages(&usages),
+                            FieldOutcome::Skipped {
+                                reason: FieldSkipReason::DifferentValues,
+                            },
+                        );
+                    }
+                    continue;
+                };
+                root_changes.push(EntryChange {
+                    table: "workspace.package".to_owned(),
+                    key: field.to_string(),
+                    value: Some(most_common.to_string()),
+                });
+                most_common.clone()
+            }
+        };
+
+        let (usages, outliers): (Vec<_>, Vec<_>) = usages
+            .into_iter()
+            .partition(|(_, value)| *value == inherited);
+        if !outliers.is_empty() {
+            report.package_field(
+                field,
+                field_usages(&outliers),
+                FieldOutcome::Skipped {
+                    reason: FieldSkipReason::DifferentFromWorkspace,
+                },
+            );
+        }
+        if usages.is_empty() {
+            continue;
+        }
+        let mut inherit = toml_edit::InlineTable::new();
+        inherit.insert("workspace", true.into());
+        let item = toml_edit::Item::Value(inherit.into());
+        for (member, _) in &usages {
+            member_changes
+                .entry(member.manifest_path.clone())
+                .or_default()
+                .push(EntryChange::new("package", field, &item));
+        }
+        report.package_field(
+            field,
+            field_usages(&usages),
+            FieldOutcome::Inherited {
+                value: inherited.to_string(),
+            },
+        );
+    }
+    Ok(member_changes)
+}
+
+fn field_usages(usages: &[(&MemberPackage, toml::Value)]) -> Vec<FieldUsage> {
+    usages
+        .iter()
+        .map(|(member, value)| FieldUsage {
+            member: member.name.clone(),
+            value: value.to_string(),
+        })
+        .collect()
+}
+
+/// Returns `value` as it would be written in `[workspace.package]`.
+///
+/// Cargo resolves an inherited `readme` relative to the workspace root,
+/// so a member's path is only shared with others if it points to the same file.
+fn rebase(
+    field: &str,
+    value: &toml::Value,
+    manifest_dir: &Path,
+    workspace_root: &Path,
+) -> Result<toml::Value, Error> {
+    let (Some(path), "readme") = (value.as_str(), field) else {
+        return Ok(value.clone());
+    };
+    Ok(toml::Value::String(rebase_path(
+        path,
+        manifest_dir,
+        workspace_root,
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::PackageFieldReport;
+
+    fn member(name: &str, manifest_path: &str, package: &str) -> MemberPackage {
+        MemberPackage {
+            name: name.to_owned(),
+            manifest_path: PathBuf::from(manifest_path),
+            package: toml::from_str(package).unwrap(),
+        }
+    }
+
+    fn entry(key: &str, value: &str) -> EntryChange {
+        EntryChange {
+            table: "workspace.package".to_owned(),
+            key: key.to_owned(),
+            value: Some(value.to_owned()),
+        }
+    }
+
+    fn members_of(report: &PackageFieldReport) -> Vec<&str> {
+        report
+            .usages
+            .iter()
+            .map(|usage| usage.member.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn most_common_value_is_hoisted() {
+        let members = [
+            member("a", "/ws/a/Cargo.toml", "edition = \"2021\""),
+            member("b", "/ws/b/Cargo.toml", "edition = \"2018\""),
+            member("c", "/ws/c/Cargo.toml", "edition = \"2021\""),
+        ];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = inherit_package_fields(
+            Path::new("/ws"),
+            None,
+            &members,
+            &mut report,
+            &mut root_changes,
+        )
+        .unwrap();
+
+        assert_eq!(root_changes, [entry("edition", "\"2021\"")]);
+        let changed: Vec<_> = member_changes.keys().collect();
+        assert_eq!(changed, ["/ws/a/Cargo.toml", "/ws/c/Cargo.toml"]);
+        assert!(matches!(
+            &report.package_fields[..],
+            [
+                PackageFieldReport {
+                    outcome: FieldOutcome::Skipped {
+                        reason: FieldSkipReason::DifferentFromWorkspace
+                    },
+                    ..
+                },
+                PackageFieldReport {
+                    outcome: FieldOutcome::Inherited { .. },
+                    ..
+                },
+            ]
+        ));
+        assert_eq!(members_of(&report.package_fields[0]), ["b"]);
+        assert_eq!(members_of(&report.package_fields[1]), ["a", "c"]);
+    }
+
+    #[test]
+    fn tied_values_are_not_hoisted() {
+        let members = [
+            member("a", "/ws/a/Cargo.toml", "edition = \"2021\""),
+            member("b", "/ws/b/Cargo.toml", "edition = \"2018\""),
+        ];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = inherit_package_fields(
+            Path::new("/ws"),
+            None,
+            &members,
+            &mut report,
+            &mut root_changes,
+        )
+        .unwrap();
+
+        assert!(root_changes.is_empty());
+        assert!(member_changes.is_empty());
+        assert!(matches!(
+            &report.package_fields[..],
+            [PackageFieldReport {
+                outcome: FieldOutcome::Skipped {
+                    reason: FieldSkipReason::DifferentValues
+                },
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn readme_is_rebased_on_the_workspace_root() {
+        let members = [
+            member(
+                "a",
+                "/ws/crates/a/Cargo.toml",
+                "readme = \"../../README.md\"",
+            ),
+            member(
+                "b",
+                "/ws/crates/b/Cargo.toml",
+                "readme = \"../../README.md\"",
+            ),
+            // Its own README, not the workspace one.
+            member("c", "/ws/crates/c/Cargo.toml", "readme = \"README.md\""),
+        ];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = inherit_package_fields(
+            Path::new("/ws"),
+            None,
+            &members,
+            &mut report,
+            &mut root_changes,
+        )
+        .unwrap();
+
+        assert_eq!(root_changes, [entry("readme", "\"README.md\"")]);
+        let changed: Vec<_> = member_changes.keys().collect();
+        assert_eq!(
+            changed,
+            ["/ws/crates/a/Cargo.toml", "/ws/crates/b/Cargo.toml"]
+        );
+    }
+}
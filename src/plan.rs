@@ -0,0 +1,379 @@
+use crate::report::SkipReason;
+use crate::{insert_preserving_decor, parse_document, read_manifest, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Key};
+
+/// Everything `cargo autoinherit` would change in a workspace, as returned by [`plan`].
+///
+/// A plan can be inspected, filtered or serialized before being handed to [`apply`]:
+/// only the entries it contains are written.
+///
+/// [`plan`]: crate::plan
+/// [`apply`]: crate::apply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    /// Represent inherited dependencies as `package.workspace = true` if possible.
+    pub prefer_simple_dotted: bool,
+    /// The entries to add to, or update in, the root manifest, e.g. in `[workspace.dependencies]`.
+    /// If the root manifest is also a package, its own rewritten entries are included here.
+    pub root: ManifestPlan,
+    /// The entries to rewrite in each member manifest.
+    /// Members that don't inherit anything are omitted.
+    pub members: Vec<ManifestPlan>,
+    /// The dependencies that won't be inherited, and why.
+    pub skipped: Vec<SkippedDependency>,
+    /// The contents of the manifests the plan was computed from, keyed by path.
+    /// The plan is only applied if none of them has changed since.
+    pub originals: BTreeMap<PathBuf, String>,
+}
+
+/// The entries to write into a single manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestPlan {
+    pub path: PathBuf,
+    pub changes: Vec<EntryChange>,
+}
+
+/// A single entry to add to, rewrite in, or remove from a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryChange {
+    /// The dotted path of the table the entry belongs to, e.g. `target."cfg(unix)".dependencies`,
+    /// or an empty string for the top level of the manifest.
+    pub table: String,
+    pub key: String,
+    /// The new value of the entry, as an inline TOML value, or `None` if it's removed.
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedDependency {
+    /// The name of the package.
+    pub name: String,
+    pub reason: SkipReason,
+}
+
+/// A manifest that has been modified in memory, but not yet written to disk.
+pub(crate) struct ManifestEdit {
+    pub(crate) path: PathBuf,
+    pub(crate) original: String,
+    pub(crate) document: DocumentMut,
+    pub(crate) changes: Vec<EntryChange>,
+}
+
+impl Plan {
+    /// The manifests that the plan modifies, starting with the root one.
+    pub fn manifests(&self) -> impl Iterator<Item = &ManifestPlan> {
+        std::iter::once(&self.root)
+            .chain(&self.members)
+            .filter(|manifest| !manifest.changes.is_empty())
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Self, Error> {
+        fs_err::File::open(path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::from_reader(std::io::BufReader::new(file)))
+            .map_err(|e| Error::ReadPlan {
+                path: path.to_owned(),
+                source: e,
+            })
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<(), Error> {
+        fs_err::File::create(path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(std::io::BufWriter::new(file), self))
+            .map_err(|e| Error::WritePlan {
+                path: path.to_owned(),
+                source: e,
+            })
+    }
+
+    /// Reads every manifest the plan modifies and performs its changes, in memory.
+    ///
+    /// Fails if any of the manifests the plan was computed from has changed since,
+    /// or if the plan modifies a manifest it wasn't computed from.
+    pub(crate) fn edit_manifests(&self) -> Result<Vec<ManifestEdit>, Error> {
+        if let Some(manifest) = self
+            .manifests()
+            .find(|manifest| !self.originals.contains_key(&manifest.path))
+        {
+            return Err(Error::UnverifiedManifest {
+                path: manifest.path.clone(),
+            });
+        }
+        for (path, original) in &self.originals {
+            if read_manifest(path)? != *original {
+                return Err(Error::StalePlan { path: path.clone() });
+            }
+        }
+        self.manifests()
+            .map(|manifest| {
+                let original = read_manifest(&manifest.path)?;
+                let mut document = parse_document(&manifest.path, &original)?;
+                for change in &manifest.changes {
+                    change.apply(&manifest.path, &mut document, self.prefer_simple_dotted)?;
+                }
+                Ok(ManifestEdit {
+                    path: manifest.path.clone(),
+                    original,
+                    document,
+                    changes: manifest.changes.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl EntryChange {
+    pub(crate) fn new(table: &str, key: &str, value: &toml_edit::Item) -> Self {
+        Self {
+            table: table.to_owned(),
+            key: key.to_owned(),
+            value: Some(value.to_string().trim().to_owned()),
+        }
+    }
+
+    pub(crate) fn remove(table: &str, key: &str) -> Self {
+        Self {
+            table: table.to_owned(),
+            key: key.to_owned(),
+            value: None,
+        }
+    }
+
+    /// Where the entry is in its manifest, e.g. `[dependencies] serde`.
+    pub(crate) fn location(&self) -> String {
+        if self.table.is_empty() {
+            self.key.clone()
+        } else {
+            format!("[{}] {}", self.table, self.key)
+        }
+    }
+
+    fn apply(
+        &self,
+        path: &Path,
+        document: &mut DocumentMut,
+        prefer_simple_dotted: bool,
+    ) -> Result<(), Error> {
+        let invalid = |source| Error::InvalidPlanEntry {
+            path: path.to_owned(),
+            table: self.table.clone(),
+            key: self.key.clone(),
+            source: Box::new(source),
+        };
+        let keys = if self.table.is_empty() {
+            Vec::new()
+        } else {
+            Key::parse(&self.table).map_err(invalid)?
+        };
+        let mut table = document.as_table_mut();
+        for key in keys {
+            if self.value.is_none() && !table.contains_key(key.get()) {
+                // Nothing to remove.
+                return Ok(());
+            }
+            table = table
+                .entry(key.get())
+                .or_insert_with(|| {
+                    // Only rendered if it ends up with entries of its own, so that e.g.
+                    // `[workspace.lints.rust]` isn't preceded by an empty `[workspace.lints]`.
+                    let mut new_table = toml_edit::Table::new();
+                    new_table.set_implicit(true);
+                    toml_edit::Item::Table(new_table)
+                })
+                .as_table_mut()
+                .ok_or_else(|| Error::UnexpectedToml {
+                    path: path.to_owned(),
+                    key: self.table.clone(),
+                    expected: "a table",
+                })?;
+        }
+
+        // Inherited package fields are always written as `edition.workspace = true`,
+        // the form `cargo new` uses.
+        let dotted = prefer_simple_dotted || self.table == "package";
+        let Some(value) = &self.value else {
+            table.remove(&self.key);
+            return Ok(());
+        };
+        let mut value: toml_edit::Value = value.parse().map_err(invalid)?;
+        if let toml_edit::Value::InlineTable(inherited) = &mut value {
+            // Rebuilt rather than modified in place: the decor of the parsed key
+            // is only meant for the inline form.
+            if dotted && inherited.len() == 1 && inherited.contains_key("workspace") {
+                let mut workspace = toml_edit::Value::from(true);
+                // A dotted table is rendered without its own decor:
+                // keep the trailing comment of the existing entry on its value instead.
+                if let Some(suffix) = table
+                    .get(&self.key)
+                    .and_then(|item| item.as_value())
+                    .and_then(|value| value.decor().suffix())
+                {
+                    workspace.decor_mut().set_suffix(suffix.clone());
+                }
+                let mut dotted = toml_edit::InlineTable::new();
+                dotted.insert("workspace", workspace);
+                dotted.set_dotted(true);
+                *inherited = dotted;
+            }
+        }
+        insert_preserving_decor(table, &self.key, toml_edit::Item::Value(value));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(manifest: &str, change: &EntryChange, prefer_simple_dotted: bool) -> String {
+        let mut document = manifest.parse().unwrap();
+        change
+            .apply(Path::new("Cargo.toml"), &mut document, prefer_simple_dotted)
+            .unwrap();
+        document.to_string()
+    }
+
+    fn entry(table: &str, key: &str, value: &str) -> EntryChange {
+        EntryChange {
+            table: table.to_owned(),
+            key: key.to_owned(),
+            value: Some(value.to_owned()),
+        }
+    }
+
+    #[test]
+    fn entry_changes_are_serialized_as_strings() {
+        let changes = [
+            entry("workspace.dependencies", "serde", "\"1\""),
+            EntryChange::remove("profile.release", "lto"),
+        ];
+        assert_eq!(
+            serde_json::to_value(changes).unwrap(),
+            serde_json::json!([
+                { "table": "workspace.dependencies", "key": "serde", "value": "\"1\"" },
+                { "table": "profile.release", "key": "lto", "value": null },
+            ])
+        );
+    }
+
+    #[test]
+    fn missing_tables_are_created_as_implicit() {
+        let change = entry("workspace.lints.rust", "unsafe_code", "\"forbid\"");
+        assert_eq!(
+            apply("[workspace]\nmembers = [\"a\"]\n", &change, false),
+            "[workspace]\nmembers = [\"a\"]\n\n[workspace.lints.rust]\nunsafe_code = \"forbid\"\n"
+        );
+    }
+
+    #[test]
+    fn removing_from_a_missing_table_does_nothing() {
+        let manifest = "[package]\nname = \"a\"\n";
+        let change = EntryChange::remove("profile.release", "lto");
+        assert_eq!(apply(manifest, &change, false), manifest);
+    }
+
+    #[test]
+    fn tables_are_parsed_as_dotted_keys() {
+        let manifest = "[target.'cfg(unix)'.dependencies]\nlibc = \"0.2\"\n";
+        let change = entry(
+            "target.\"cfg(unix)\".dependencies",
+            "libc",
+            "{ workspace = true }",
+        );
+        assert_eq!(
+            apply(manifest, &change, false),
+            "[target.'cfg(unix)'.dependencies]\nlibc = { workspace = true }\n"
+        );
+    }
+
+    #[test]
+    fn dotted_rewrites_keep_trailing_comments() {
+        let dependency = entry("dependencies", "serde", "{ workspace = true }");
+        assert_eq!(
+            apply(
+                "[dependencies]\nserde = \"1\" # pinned\n",
+                &dependency,
+                true
+            ),
+            "[dependencies]\nserde.workspace = true # pinned\n"
+        );
+        assert_eq!(
+            apply(
+                "[dependencies]\nserde = \"1\" # pinned\n",
+                &dependency,
+                false
+            ),
+            "[dependencies]\nserde = { workspace = true } # pinned\n"
+        );
+
+        let field = entry("package", "edition", "{ workspace = true }");
+        assert_eq!(
+            apply("[package]\nedition = \"2021\" # latest\n", &field, false),
+            "[package]\nedition.workspace = true # latest\n"
+        );
+    }
+
+    /// A plan that adds `anyhow` to the root manifest and inherits it in member `a`,
+    /// written to a fresh directory.
+    fn plan(name: &str) -> (PathBuf, Plan) {
+        let dir = std::env::temp_dir().join(format!(
+            "cargo-autoinherit-plan-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs_err::remove_dir_all(&dir);
+        fs_err::create_dir_all(dir.join("a")).unwrap();
+        let root = "[workspace]\nmembers = [\"a\"]\n";
+        let member = "[package]\nname = \"a\"\n\n[dependencies]\nanyhow = \"1\"\n";
+        fs_err::write(dir.join("Cargo.toml"), root).unwrap();
+        fs_err::write(dir.join("a/Cargo.toml"), member).unwrap();
+        let plan = Plan {
+            prefer_simple_dotted: false,
+            root: ManifestPlan {
+                path: dir.join("Cargo.toml"),
+                changes: vec![entry("workspace.dependencies", "anyhow", "\"1\"")],
+            },
+            members: vec![ManifestPlan {
+                path: dir.join("a/Cargo.toml"),
+                changes: vec![entry("dependencies", "anyhow", "{ workspace = true }")],
+            }],
+            skipped: Vec::new(),
+            originals: BTreeMap::from([
+                (dir.join("Cargo.toml"), root.to_owned()),
+                (dir.join("a/Cargo.toml"), member.to_owned()),
+            ]),
+        };
+        (dir, plan)
+    }
+
+    #[test]
+    fn stale_plans_are_rejected() {
+        let (dir, plan) = plan("stale");
+        plan.write(&dir.join("plan.json")).unwrap();
+        let plan = Plan::read(&dir.join("plan.json")).unwrap();
+        assert_eq!(plan.edit_manifests().unwrap().len(), 2);
+
+        let member_path = dir.join("a/Cargo.toml");
+        fs_err::write(&member_path, "[package]\nname = \"a\"\n").unwrap();
+        assert!(matches!(
+            plan.edit_manifests(),
+            Err(Error::StalePlan { ref path }) if *path == member_path
+        ));
+        fs_err::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plans_without_originals_are_rejected() {
+        let (dir, mut plan) = plan("unverified");
+        let member_path = dir.join("a/Cargo.toml");
+        plan.originals.remove(&member_path);
+        assert!(matches!(
+            plan.edit_manifests(),
+            Err(Error::UnverifiedManifest { ref path }) if *path == member_path
+        ));
+        fs_err::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,462 @@
+use crate::dedup::try_merge_sources;
+use crate::plan::ManifestPlan;
+use crate::{dep2shared_dep, DependencySource, OnConflict, SharedDependency, SourceType};
+use cargo_manifest::DepsSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A structured summary of what `cargo autoinherit` did, or would do, to a workspace.
+///
+/// It is printed as JSON when `--message-format json` is used; otherwise it's rendered
+/// as human-readable messages.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Report {
+    pub(crate) members: Vec<MemberReport>,
+    pub(crate) dependencies: Vec<DependencyReport>,
+    pub(crate) manifests: Vec<ManifestPlan>,
+    /// Only populated with `--package-fields`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) package_fields: Vec<PackageFieldReport>,
+    /// Only populated with `--lints`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) lints: Vec<MemberLintsReport>,
+    /// The entries of `[profile.*]`, `[patch]` and `[replace]` found in members.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) root_entries: Vec<RootEntryReport>,
+    /// Where each dependency is used, keyed by package name.
+    /// Drained into `dependencies` as decisions are made.
+    #[serde(skip)]
+    usages: BTreeMap<String, Vec<DependencyUsage>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MemberReport {
+    pub(crate) name: String,
+    pub(crate) manifest_path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) skipped: Option<SkipReason>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DependencyReport {
+    pub(crate) name: String,
+    pub(crate) usages: Vec<DependencyUsage>,
+    #[serde(flatten)]
+    pub(crate) outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct DependencyUsage {
+    pub(crate) member: String,
+    pub(crate) kind: DependencyKind,
+    /// The key the dependency is declared under, if it differs from the package name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) alias: Option<String>,
+    /// The source the member asked for, if it can be shared.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<DependencySource>,
+    /// The platform the dependency is restricted to, for `[target.'...'.*dependencies]` tables.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) target: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DependencyKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub(crate) enum Outcome {
+    Inherited {
+        source: SharedDependency,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        conflict: Option<ConflictResolution>,
+    },
+    Skipped {
+        reason: SkipReason,
+    },
+}
+
+/// A `[package]` field set by several members.
+#[derive(Debug, Serialize)]
+pub(crate) struct PackageFieldReport {
+    pub(crate) field: String,
+    pub(crate) usages: Vec<FieldUsage>,
+    #[serde(flatten)]
+    pub(crate) outcome: FieldOutcome,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FieldUsage {
+    pub(crate) member: String,
+    /// The value the member sets, as it would be written in `[workspace.package]`.
+    pub(crate) value: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub(crate) enum FieldOutcome {
+    Inherited { value: String },
+    Skipped { reason: FieldSkipReason },
+}
+
+/// Why a `[package]` field wasn't hoisted into `[workspace.package]`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum FieldSkipReason {
+    /// There's no value in `[workspace.package]`, and no value is set by more members than the others.
+    DifferentValues,
+    /// The members set the field to a different value than `[workspace.package]`.
+    DifferentFromWorkspace,
+}
+
+/// Whether a member with a `[lints]` table inherits `[workspace.lints]`.
+#[derive(Debug, Serialize)]
+pub(crate) struct MemberLintsReport {
+    pub(crate) member: String,
+    #[serde(flatten)]
+    pub(crate) outcome: LintsOutcome,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub(crate) enum LintsOutcome {
+    Inherited,
+    Skipped { reason: LintsSkipReason },
+}
+
+/// Why a member keeps its own `[lints]` table.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum LintsSkipReason {
+    /// The member's lints differ from the ones in `[workspace.lints]`.
+    DifferentLints,
+    /// There's no `[workspace.lints]`, and no set of lints is shared by more members than the others.
+    NoSharedLints,
+}
+
+/// An entry of a section that Cargo only reads from the root manifest, set by some members.
+#[derive(Debug, Serialize)]
+pub(crate) struct RootEntryReport {
+    /// The dotted path of the entry, e.g. `profile.release.opt-level`.
+    pub(crate) entry: String,
+    pub(crate) usages: Vec<FieldUsage>,
+    #[serde(flatten)]
+    pub(crate) outcome: RootEntryOutcome,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub(crate) enum RootEntryOutcome {
+    /// The entry was moved to the root manifest, or it was already there.
+    Moved,
+    /// Members set the entry to different values, or to a different value than the root manifest.
+    Conflict {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        root_value: Option<String>,
+    },
+}
+
+/// How conflicting version requirements were resolved, according to `--on-conflict`.
+#[derive(Debug, Serialize)]
+pub(crate) struct ConflictResolution {
+    pub(crate) strategy: OnConflict,
+    pub(crate) sources: Vec<SharedDependency>,
+    /// The members whose requirement isn't compatible with the inherited one.
+    pub(crate) moved_members: Vec<String>,
+}
+
+/// Why a dependency or a workspace member was left untouched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// The dependency appears at least once from a source type we don't support.
+    UnsupportedSource,
+    /// The dependency appears with requirements that couldn't be merged into a single one.
+    MultipleSources { sources: Vec<SharedDependency> },
+    /// The dependency comes from a single registry, but no version satisfies all of its requirements.
+    IncompatibleVersions { sources: Vec<SharedDependency> },
+    /// At least one entry for the dependency uses keys that would be lost if it was inherited.
+    UnsupportedKeys { keys: Vec<String> },
+    /// One of the keys the dependency is declared under is also used for other packages.
+    AmbiguousAlias {
+        alias: String,
+        packages: Vec<String>,
+    },
+    /// The dependency matches `exclude-dependencies`, or doesn't match `--only`.
+    ExcludedByConfig,
+    /// The dependency is used by fewer members than `--min-members`,
+    /// and it isn't in `[workspace.dependencies]` yet.
+    UsedByFewMembers { min_members: usize },
+    /// The member was excluded via `--exclude-members`, the workspace metadata,
+    /// or `skip` in its own `[package.metadata.cargo-autoinherit]`.
+    ExcludedMember,
+}
+
+impl Report {
+    pub(crate) fn record_usages(&mut self, deps: &DepsSet, member: &str, kind: DependencyKind) {
+        self.push_usages(deps, member, kind, None);
+    }
+
+    pub(crate) fn record_target_usages(
+        &mut self,
+        deps: &DepsSet,
+        member: &str,
+        kind: DependencyKind,
+        target: &str,
+    ) {
+        self.push_usages(deps, member, kind, Some(target));
+    }
+
+    fn push_usages(
+        &mut self,
+        deps: &DepsSet,
+        member: &str,
+        kind: DependencyKind,
+        target: Option<&str>,
+    ) {
+        for (name, dep) in deps {
+            let package_name = dep.package().unwrap_or(name);
+            self.usages
+                .entry(package_name.to_owned())
+                .or_default()
+                .push(DependencyUsage {
+                    member: member.to_owned(),
+                    kind,
+                    alias: (package_name != name).then(|| name.clone()),
+                    source: match dep2shared_dep(dep) {
+                        Ok(SourceType::Shareable(shared)) => Some(shared.source),
+                        Ok(SourceType::Inherited | SourceType::MustBeSkipped) | Err(_) => None,
+                    },
+                    target: target.map(ToOwned::to_owned),
+                });
+        }
+    }
+
+    pub(crate) fn inherited(&mut self, name: &str, source: SharedDependency) {
+        self.push_dependency(
+            name,
+            Outcome::Inherited {
+                source,
+                conflict: None,
+            },
+        );
+    }
+
+    pub(crate) fn resolved_conflict(
+        &mut self,
+        name: &str,
+        source: SharedDependency,
+        strategy: OnConflict,
+        sources: Vec<SharedDependency>,
+    ) {
+        let mut moved_members: Vec<String> = self
+            .usages
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|usage| {
+                usage.source.as_ref().is_some_and(|member_source| {
+                    try_merge_sources(member_source, &source.source).is_none()
+                })
+            })
+            .map(|usage| usage.member.clone())
+            .collect();
+        moved_members.dedup();
+        let conflict = ConflictResolution {
+            strategy,
+            sources,
+            moved_members,
+        };
+        self.push_dependency(
+            name,
+            Outcome::Inherited {
+                source,
+                conflict: Some(conflict),
+            },
+        );
+    }
+
+    pub(crate) fn skipped(&mut self, name: &str, reason: SkipReason) {
+        self.push_dependency(name, Outcome::Skipped { reason });
+    }
+
+    pub(crate) fn package_field(
+        &mut self,
+        field: &str,
+        usages: Vec<FieldUsage>,
+        outcome: FieldOutcome,
+    ) {
+        self.package_fields.push(PackageFieldReport {
+            field: field.to_owned(),
+            usages,
+            outcome,
+        });
+    }
+
+    pub(crate) fn member_lints(&mut self, member: &str, outcome: LintsOutcome) {
+        self.lints.push(MemberLintsReport {
+            member: member.to_owned(),
+            outcome,
+        });
+    }
+
+    pub(crate) fn root_entry(
+        &mut self,
+        entry: &str,
+        usages: Vec<FieldUsage>,
+        outcome: RootEntryOutcome,
+    ) {
+        self.root_entries.push(RootEntryReport {
+            entry: entry.to_owned(),
+            usages,
+            outcome,
+        });
+    }
+
+    fn push_dependency(&mut self, name: &str, outcome: Outcome) {
+        let usages = self.usages.remove(name).unwrap_or_default();
+        self.dependencies.push(DependencyReport {
+            name: name.to_owned(),
+            usages,
+            outcome,
+        });
+    }
+
+    /// Prints the messages that are relevant to a human user: excluded members,
+    /// dependencies, package fields or lints that won't be inherited,
+    /// and member sections that can't be moved to the root manifest.
+    pub(crate) fn print_human(&self) {
+        for member in &self.members {
+            if let Some(SkipReason::ExcludedMember) = member.skipped {
+                println!("Excluded workspace member `{}`", member.name);
+            }
+        }
+        for dependency in &self.dependencies {
+            let name = &dependency.name;
+            let reason = match &dependency.outcome {
+                Outcome::Skipped { reason } => reason,
+                Outcome::Inherited {
+                    source,
+                    conflict: Some(conflict),
+                } => {
+                    let picked = match conflict.strategy {
+                        OnConflict::Lowest => "lowest",
+                        _ => "highest",
+                    };
+                    println!(
+                        "`{name}` has conflicting requirements, the {picked} one was inherited ({}).",
+                        source.source
+                    );
+                    for member in &conflict.moved_members {
+                        println!("  - `{member}` was moved to it");
+                    }
+                    continue;
+                }
+                Outcome::Inherited { conflict: None, .. } => continue,
+            };
+            match reason {
+                SkipReason::UnsupportedSource => {
+                    eprintln!("`{name}` won't be auto-inherited because it appears at least once from a source type \
+                        that we currently don't support (e.g. a `registry-index` URL).");
+                }
+                SkipReason::MultipleSources { sources } => {
+                    eprintln!("`{name}` won't be auto-inherited because there are multiple sources for it:");
+                    for spec in sources {
+                        eprintln!("  - {}", spec.source);
+                    }
+                }
+                SkipReason::IncompatibleVersions { sources } => {
+                    eprintln!("`{name}` won't be auto-inherited because no version satisfies all of its requirements:");
+                    for spec in sources {
+                        eprintln!("  - {}", spec.source);
+                    }
+                }
+                SkipReason::UnsupportedKeys { keys } => {
+                    let keys = keys
+                        .iter()
+                        .map(|key| format!("`{key}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    eprintln!("`{name}` won't be auto-inherited because it uses keys that can't be inherited: {keys}.");
+                }
+                SkipReason::AmbiguousAlias { alias, packages } => {
+                    eprintln!("`{name}` won't be auto-inherited because `{alias}` refers to different packages:");
+                    for package in packages {
+                        eprintln!("  - {package}");
+                    }
+                }
+                SkipReason::ExcludedByConfig => {
+                    eprintln!("`{name}` won't be auto-inherited because it's excluded by config.");
+                }
+                SkipReason::UsedByFewMembers { min_members } => {
+                    eprintln!("`{name}` won't be auto-inherited because it's used by fewer than {min_members} members.");
+                }
+                SkipReason::ExcludedMember => {}
+            }
+        }
+        for field in &self.package_fields {
+            let FieldOutcome::Skipped { reason } = &field.outcome else {
+                continue;
+            };
+            match reason {
+                FieldSkipReason::DifferentValues => {
+                    eprintln!(
+                        "`{}` won't be inherited because members set it to different values:",
+                        field.field
+                    );
+                    for usage in &field.usages {
+                        eprintln!("  - `{}`: {}", usage.member, usage.value);
+                    }
+                }
+                FieldSkipReason::DifferentFromWorkspace => {
+                    for usage in &field.usages {
+                        eprintln!(
+                            "`{}` won't inherit `{}` because it sets it to a different value: {}",
+                            usage.member, field.field, usage.value
+                        );
+                    }
+                }
+            }
+        }
+        for member in &self.lints {
+            let LintsOutcome::Skipped { reason } = &member.outcome else {
+                continue;
+            };
+            match reason {
+                LintsSkipReason::DifferentLints => {
+                    eprintln!(
+                        "`{}` won't inherit `[workspace.lints]` because its lints differ.",
+                        member.member
+                    );
+                }
+                LintsSkipReason::NoSharedLints => {
+                    eprintln!(
+                        "`{}` won't inherit `[workspace.lints]` because no set of lints is shared by most members.",
+                        member.member
+                    );
+                }
+            }
+        }
+        for entry in &self.root_entries {
+            let RootEntryOutcome::Conflict { root_value } = &entry.outcome else {
+                continue;
+            };
+            eprintln!(
+                "`{}` won't be moved to the root manifest because it's set to different values:",
+                entry.entry
+            );
+            for usage in &entry.usages {
+                eprintln!("  - `{}`: {}", usage.member, usage.value);
+            }
+            if let Some(root_value) = root_value {
+                eprintln!("  - root manifest: {root_value}");
+            }
+        }
+    }
+}
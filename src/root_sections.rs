@@ -0,0 +1,306 @@
+use crate::plan::EntryChange;
+use crate::report::{FieldUsage, Report, RootEntryOutcome};
+use crate::{rebase_path, Error};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use toml_edit::Key;
+
+/// The sections that Cargo only reads from the root manifest, along with how deep their entries are.
+///
+/// Every setting of a profile is an entry of its own, while `[patch.crates-io] foo = { ... }`
+/// and `[replace] "foo:0.1.0" = { ... }` are moved as a whole.
+const ROOT_SECTIONS: &[(&str, Option<usize>)] =
+    &[("profile", None), ("patch", Some(2)), ("replace", Some(1))];
+
+/// The root-only sections of a member that isn't the root package.
+pub(crate) struct MemberSections {
+    pub(crate) name: String,
+    pub(crate) manifest_path: PathBuf,
+    pub(crate) sections: toml::Table,
+}
+
+impl MemberSections {
+    /// Returns `None` if the manifest doesn't have any root-only section.
+    pub(crate) fn new(name: &str, manifest_path: &Path, manifest: &toml::Table) -> Option<Self> {
+        let sections: toml::Table = ROOT_SECTIONS
+            .iter()
+            .filter_map(|(section, _)| Some((section.to_string(), manifest.get(*section)?.clone())))
+            .collect();
+        (!sections.is_empty()).then(|| Self {
+            name: name.to_owned(),
+            manifest_path: manifest_path.to_owned(),
+            sections,
+        })
+    }
+}
+
+/// Works out how to move the `[profile.*]`, `[patch]` and `[replace]` sections of members,
+/// which Cargo ignores, to the root manifest.
+///
+/// An entry is moved if every member that sets it agrees, and the root manifest
+/// doesn't set it to something else. Otherwise it's left where it is, and reported.
+pub(crate) fn move_root_sections(
+    workspace_root: &Path,
+    root_manifest: &toml::Table,
+    members: &[MemberSections],
+    report: &mut Report,
+    root_changes: &mut Vec<EntryChange>,
+) -> Result<BTreeMap<PathBuf, Vec<EntryChange>>, Error> {
+    let root_entries: BTreeMap<_, _> = entries(root_manifest, workspace_root, workspace_root)?
+        .into_iter()
+        .collect();
+    let mut path2usages: BTreeMap<Vec<String>, Vec<(&MemberSections, toml::Value)>> =
+        BTreeMap::new();
+    for member in members {
+        let manifest_dir = member.manifest_path.parent().unwrap_or(Path::new(""));
+        for (path, value) in entries(&member.sections, manifest_dir, workspace_root)? {
+            path2usages.entry(path).or_default().push((member, value));
+        }
+    }
+
+    let mut conflicts = BTreeSet::new();
+    for (path, usages) in &path2usages {
+        let (_, value) = &usages[0];
+        let root_value = root_entries.get(path);
+        let report_usages = usages
+            .iter()
+            .map(|(member, value)| FieldUsage {
+                member: member.name.clone(),
+                value: value.to_string(),
+            })
+            .collect();
+        let entry = dotted(path);
+        if usages.iter().any(|(_, other)| other != value)
+            || root_value.is_some_and(|root_value| root_value != value)
+        {
+            conflicts.insert(path.clone());
+            let outcome = RootEntryOutcome::Conflict {
+                root_value: root_value.map(ToString::to_string),
+            };
+            report.root_entry(&entry, report_usages, outcome);
+            continue;
+        }
+        if root_value.is_none() {
+            let (key, table) = path.split_last().unwrap();
+            root_changes.push(EntryChange {
+                table: dotted(table),
+                key: key.clone(),
+                value: Some(value.to_string()),
+            });
+        }
+        report.root_entry(&entry, report_usages, RootEntryOutcome::Moved);
+    }
+
+    let mut member_changes = BTreeMap::new();
+    for member in members {
+        let mut changes = Vec::new();
+        for (section, value) in &member.sections {
+            removals(&mut vec![section.clone()], value, &conflicts, &mut changes);
+        }
+        if !changes.is_empty() {
+            member_changes.insert(member.manifest_path.clone(), changes);
+        }
+    }
+    Ok(member_changes)
+}
+
+/// Flattens the root-only sections of `manifest` into their entries, keyed by path.
+///
+/// `path` keys are rewritten as relative to the workspace root.
+fn entries(
+    manifest: &toml::Table,
+    manifest_dir: &Path,
+    workspace_root: &Path,
+) -> Result<Vec<(Vec<String>, toml::Value)>, Error> {
+    fn flatten(
+        path: &mut Vec<String>,
+        value: &toml::Value,
+        depth: Option<usize>,
+        entries: &mut Vec<(Vec<String>, toml::Value)>,
+    ) {
+        match value.as_table() {
+            Some(table) if depth != Some(0) => {
+                for (key, value) in table {
+                    path.push(key.clone());
+                    flatten(path, value, depth.map(|depth| depth - 1), entries);
+                    path.pop();
+                }
+            }
+            _ => entries.push((path.clone(), value.clone())),
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (section, depth) in ROOT_SECTIONS {
+        if let Some(value) = manifest.get(*section) {
+            flatten(&mut vec![section.to_string()], value, *depth, &mut entries);
+        }
+    }
+    for (_, value) in &mut entries {
+        if let Some(path) = value.get_mut("path").filter(|path| path.is_str()) {
+            let rebased = rebase_path(path.as_str().unwrap(), manifest_dir, workspace_root)?;
+            *path = toml::Value::String(rebased);
+        }
+    }
+    Ok(entries)
+}
+
+/// Removes `value`, at `path`, unless it contains conflicting entries: in that case,
+/// only the tables and entries around them are removed.
+fn removals(
+    path: &mut Vec<String>,
+    value: &toml::Value,
+    conflicts: &BTreeSet<Vec<String>>,
+    changes: &mut Vec<EntryChange>,
+) {
+    if !conflicts.iter().any(|conflict| conflict.starts_with(path)) {
+        let (key, table) = path.split_last().unwrap();
+        changes.push(EntryChange::remove(&dotted(table), key));
+        return;
+    }
+    if conflicts.contains(path) {
+        return;
+    }
+    if let Some(table) = value.as_table() {
+        for (key, value) in table {
+            path.push(key.clone());
+            removals(path, value, conflicts, changes);
+            path.pop();
+        }
+    }
+}
+
+fn dotted(path: &[String]) -> String {
+    path.iter()
+        .map(|key| Key::new(key.as_str()).to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::RootEntryReport;
+
+    fn member(name: &str, manifest_path: &str, manifest: &str) -> MemberSections {
+        let manifest: toml::Table = toml::from_str(manifest).unwrap();
+        MemberSections::new(name, Path::new(manifest_path), &manifest).unwrap()
+    }
+
+    fn entry(table: &str, key: &str, value: &str) -> EntryChange {
+        EntryChange {
+            table: table.to_owned(),
+            key: key.to_owned(),
+            value: Some(value.to_owned()),
+        }
+    }
+
+    #[test]
+    fn conflicting_leaves_stay_in_members() {
+        let members = [
+            member(
+                "a",
+                "/ws/a/Cargo.toml",
+                "[profile.release]\nlto = true\nopt-level = 3\n",
+            ),
+            member(
+                "b",
+                "/ws/b/Cargo.toml",
+                "[profile.release]\nlto = true\nopt-level = 2\n",
+            ),
+        ];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = move_root_sections(
+            Path::new("/ws"),
+            &toml::Table::new(),
+            &members,
+            &mut report,
+            &mut root_changes,
+        )
+        .unwrap();
+
+        assert_eq!(root_changes, [entry("profile.release", "lto", "true")]);
+        for path in ["/ws/a/Cargo.toml", "/ws/b/Cargo.toml"] {
+            assert_eq!(
+                member_changes[Path::new(path)],
+                [EntryChange::remove("profile.release", "lto")]
+            );
+        }
+        let outcomes: Vec<_> = report
+            .root_entries
+            .iter()
+            .map(|entry| (entry.entry.as_str(), &entry.outcome))
+            .collect();
+        assert!(matches!(
+            outcomes[..],
+            [
+                ("profile.release.lto", RootEntryOutcome::Moved),
+                (
+                    "profile.release.opt-level",
+                    RootEntryOutcome::Conflict { root_value: None }
+                ),
+            ]
+        ));
+    }
+
+    #[test]
+    fn patch_paths_are_rebased_on_the_workspace_root() {
+        let members = [member(
+            "a",
+            "/ws/crates/a/Cargo.toml",
+            "[patch.crates-io]\nfoo = { path = \"../../vendor/foo\" }\n",
+        )];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = move_root_sections(
+            Path::new("/ws"),
+            &toml::Table::new(),
+            &members,
+            &mut report,
+            &mut root_changes,
+        )
+        .unwrap();
+
+        assert_eq!(
+            root_changes,
+            [entry("patch.crates-io", "foo", "{ path = \"vendor/foo\" }")]
+        );
+        assert_eq!(
+            member_changes[Path::new("/ws/crates/a/Cargo.toml")],
+            [EntryChange::remove("", "patch")]
+        );
+    }
+
+    #[test]
+    fn root_values_win_over_members() {
+        let root: toml::Table = toml::from_str("[profile.release]\nlto = false\n").unwrap();
+        let members = [member(
+            "a",
+            "/ws/a/Cargo.toml",
+            "[profile.release]\nlto = true\n",
+        )];
+        let mut report = Report::default();
+        let mut root_changes = Vec::new();
+        let member_changes = move_root_sections(
+            Path::new("/ws"),
+            &root,
+            &members,
+            &mut report,
+            &mut root_changes,
+        )
+        .unwrap();
+
+        assert!(root_changes.is_empty());
+        assert!(member_changes.is_empty());
+        assert!(matches!(
+            &report.root_entries[..],
+            [RootEntryReport {
+                outcome: RootEntryOutcome::Conflict {
+                    root_value: Some(root_value)
+                },
+                ..
+            }] if root_value == "false"
+        ));
+    }
+}
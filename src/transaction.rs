@@ -0,0 +1,138 @@
+use crate::{Error, ManifestEdit};
+use std::path::{Path, PathBuf};
+
+/// Writes all the modified manifests to disk, as a single transaction.
+///
+/// The new contents of every manifest are first written to a temporary file next to it.
+/// The temporary files are then renamed over the original manifests, which is atomic on
+/// most platforms.
+/// If anything fails along the way, the manifests that were already replaced are restored
+/// to their original contents, leaving the workspace as it was.
+pub(crate) fn write_manifests(edits: &[ManifestEdit]) -> Result<(), Error> {
+    let mut staged = Vec::with_capacity(edits.len());
+    for edit in edits {
+        match stage(&edit.path, &edit.document.to_string()) {
+            Ok(temp_path) => staged.push(temp_path),
+            Err(e) => {
+                discard(&staged);
+                return Err(e);
+            }
+        }
+    }
+
+    for (i, (edit, temp_path)) in edits.iter().zip(&staged).enumerate() {
+        if let Err(e) = fs_err::rename(temp_path, &edit.path) {
+            discard(&staged[i..]);
+            return Err(Error::PartialWrite {
+                source: Box::new(Error::WriteManifest {
+                    path: edit.path.clone(),
+                    source: e,
+                }),
+                restore_errors: rollback(&edits[..i]),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Writes `contents` to a temporary file next to `path`, with the same permissions,
+/// and returns the path of the temporary file.
+fn stage(path: &Path, contents: &str) -> Result<PathBuf, Error> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(
+        ".{file_name}.{}.autoinherit.tmp",
+        std::process::id()
+    ));
+    let result = fs_err::write(&temp_path, contents)
+        .and_then(|_| fs_err::metadata(path))
+        .and_then(|metadata| fs_err::set_permissions(&temp_path, metadata.permissions()));
+    if let Err(e) = result {
+        let _ = fs_err::remove_file(&temp_path);
+        return Err(Error::WriteManifest {
+            path: path.to_owned(),
+            source: e,
+        });
+    }
+    Ok(temp_path)
+}
+
+/// Removes temporary files that won't be renamed over a manifest.
+fn discard(temp_paths: &[PathBuf]) {
+    for temp_path in temp_paths {
+        let _ = fs_err::remove_file(temp_path);
+    }
+}
+
+/// Restores the original contents of manifests that were already replaced,
+/// returning the errors for those that couldn't be restored.
+fn rollback(edits: &[ManifestEdit]) -> Vec<Error> {
+    edits
+        .iter()
+        .filter_map(|edit| {
+            let temp_path = match stage(&edit.path, &edit.original) {
+                Ok(temp_path) => temp_path,
+                Err(e) => return Some(e),
+            };
+            let e = fs_err::rename(&temp_path, &edit.path).err()?;
+            discard(&[temp_path]);
+            Some(Error::WriteManifest {
+                path: edit.path.clone(),
+                source: e,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(path: PathBuf, original: &str, modified: &str) -> ManifestEdit {
+        ManifestEdit {
+            path,
+            original: original.to_owned(),
+            document: modified.parse().unwrap(),
+            changes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn failed_rename_restores_replaced_manifests() {
+        let dir = std::env::temp_dir().join(format!(
+            "cargo-autoinherit-transaction-{}",
+            std::process::id()
+        ));
+        let _ = fs_err::remove_dir_all(&dir);
+        fs_err::create_dir_all(dir.join("b/Cargo.toml")).unwrap();
+        // Renaming a file over a non-empty directory fails.
+        fs_err::write(dir.join("b/Cargo.toml/keep"), "").unwrap();
+        let original = "[package]\nname = \"a\"\n";
+        fs_err::write(dir.join("Cargo.toml"), original).unwrap();
+
+        let edits = [
+            edit(
+                dir.join("Cargo.toml"),
+                original,
+                "[package]\nname = \"modified\"\n",
+            ),
+            edit(dir.join("b/Cargo.toml"), "", "[package]\nname = \"b\"\n"),
+        ];
+        let error = write_manifests(&edits).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::PartialWrite { ref restore_errors, .. } if restore_errors.is_empty()
+        ));
+
+        assert_eq!(
+            fs_err::read_to_string(dir.join("Cargo.toml")).unwrap(),
+            original
+        );
+        for dir in [&dir, &dir.join("b")] {
+            for entry in fs_err::read_dir(dir).unwrap() {
+                let file_name = entry.unwrap().file_name();
+                assert!(!file_name.to_string_lossy().ends_with(".autoinherit.tmp"));
+            }
+        }
+        fs_err::remove_dir_all(&dir).unwrap();
+    }
+}